@@ -0,0 +1,90 @@
+// Times parsing of a large synthetic keybox, made of the test fixture's keybox
+// repeated, to catch regressions in the decode path. Run with
+// `cargo bench --bench parse`, optionally passing the number of keyboxes.
+//
+// Each run takes a number of samples and reports their median, mean and spread,
+// then compares the median with the one saved by the previous run in
+// `target/keybox-bench/parse-<keyboxes>.txt`. A median more than 10% slower is
+// reported as a regression, which fails the bench with KEYBOX_BENCH_STRICT=1.
+// criterion would do this more thoroughly, but its crates cannot be fetched in
+// every build environment this repo is built in, so the bench has no dependencies.
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use keybox_parser::parse_keybox_reader;
+
+const FIXTURE: &str = include_str!("../tests/fixtures/keybox.xml");
+
+// Samples to take, after as many warm-up iterations
+const SAMPLES: usize = 20;
+// How much slower than the baseline the median may get before it is a regression
+const REGRESSION: f64 = 0.10;
+
+fn synthetic_keybox(keyboxes: usize) -> String {
+    let start = FIXTURE.find("<Keybox ").unwrap();
+    let end = FIXTURE.find("</Keybox>").unwrap() + "</Keybox>\n".len();
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<AndroidAttestation>\n");
+    xml.push_str(&format!("<NumberOfKeyboxes>{}</NumberOfKeyboxes>\n", keyboxes));
+    for i in 0..keyboxes {
+        xml.push_str(&FIXTURE[start..end].replacen("test-device", &format!("device-{}", i), 1));
+    }
+    xml.push_str("</AndroidAttestation>\n");
+    xml
+}
+
+fn time_parse(xml: &str, keyboxes: usize) -> Duration {
+    let started = Instant::now();
+    let parsed = parse_keybox_reader(black_box(xml.as_bytes())).unwrap();
+    let elapsed = started.elapsed();
+    assert_eq!(black_box(parsed).len(), keyboxes);
+    elapsed
+}
+
+fn baseline_path(keyboxes: usize) -> PathBuf {
+    let target = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from("target"), PathBuf::from);
+    target.join("keybox-bench").join(format!("parse-{}.txt", keyboxes))
+}
+
+fn main() {
+    let keyboxes = std::env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(10_000);
+    let xml = synthetic_keybox(keyboxes);
+
+    for _ in 0..SAMPLES {
+        time_parse(&xml, keyboxes);
+    }
+    let mut samples: Vec<f64> = (0..SAMPLES).map(|_| time_parse(&xml, keyboxes).as_secs_f64() * 1e3).collect();
+    samples.sort_by(f64::total_cmp);
+    let median = samples[SAMPLES / 2];
+    let mean = samples.iter().sum::<f64>() / SAMPLES as f64;
+    let deviation = (samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / SAMPLES as f64).sqrt();
+
+    let megabytes = xml.len() as f64 / 1e6;
+    println!(
+        "parse {} keyboxes ({:.1} MB): median {:.1} ms, mean {:.1} ms ± {:.1} ms, {:.1} MB/s, {} samples",
+        keyboxes,
+        megabytes,
+        median,
+        mean,
+        deviation,
+        megabytes / (median / 1e3),
+        SAMPLES
+    );
+
+    let path = baseline_path(keyboxes);
+    let baseline = std::fs::read_to_string(&path).ok().and_then(|saved| saved.trim().parse::<f64>().ok());
+    let regressed = baseline.is_some_and(|baseline| {
+        let change = median / baseline - 1.0;
+        println!("median {:+.1}% against the previous run's {:.1} ms", change * 100.0, baseline);
+        change > REGRESSION
+    });
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, format!("{}\n", median)).unwrap();
+
+    if regressed {
+        println!("regression: median more than {:.0}% slower than the previous run", REGRESSION * 100.0);
+        if std::env::var("KEYBOX_BENCH_STRICT").as_deref() == Ok("1") {
+            std::process::exit(1);
+        }
+    }
+}
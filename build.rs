@@ -1,137 +1,740 @@
-use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
-use std::path::Path;
-use base64::decode;
-use xml::reader::{EventReader, XmlEvent};
-
-fn clean_pem_data(pem: &str) -> String {
-    pem.lines()
-        .filter(|line| {
-            !line.starts_with("-----BEGIN") && !line.starts_with("-----END")
-        })
-        .collect::<Vec<&str>>()
-        .join("")
-}
-
-fn read_ec_data_from_xml(file_path: &str) -> Result<(Vec<String>, Option<String>), Box<dyn std::error::Error>> {
-    let file = File::open(file_path);
-
-    if let Err(_) = file {
-        // If file is not found, return empty vectors
-        return Ok((Vec::new(), None));
-    }
-
-    let parser = EventReader::new(file.unwrap());
-
-    let mut inside_certificate = false;
-    let mut inside_private_key = false;
-    let mut is_ecdsa = false;
-    let mut certs: Vec<String> = Vec::new();
-    let mut private_key: Option<String> = None;
-
-    for event in parser {
-        match event? {
-            XmlEvent::StartElement { name, attributes, .. } => {
-                if name.local_name == "Key" {
-                    for attr in attributes {
-                        if attr.name.local_name == "algorithm" && attr.value == "ecdsa" {
-                            is_ecdsa = true;
-                        }
-                    }
-                }
-                if name.local_name == "Certificate" && is_ecdsa {
-                    inside_certificate = true;
-                }
-                if name.local_name == "PrivateKey" && is_ecdsa {
-                    inside_private_key = true;
-                }
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+// The shared modules use alloc paths so that they also build without std
+extern crate alloc;
+
+// The parsing logic is shared with the library so it lives in one place.
+// Not every library item is needed here, hence the dead_code allowances.
+#[allow(dead_code)]
+#[path = "src/certificate.rs"]
+mod certificate;
+#[allow(dead_code)]
+#[path = "src/check.rs"]
+mod check;
+#[allow(dead_code)]
+#[path = "src/codegen.rs"]
+mod codegen;
+#[allow(dead_code)]
+#[path = "src/error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "src/input.rs"]
+mod input;
+#[cfg(feature = "json")]
+#[allow(dead_code)]
+#[path = "src/json.rs"]
+mod json;
+#[allow(dead_code)]
+#[path = "src/key.rs"]
+mod key;
+#[allow(dead_code)]
+#[path = "src/model.rs"]
+mod model;
+#[allow(dead_code)]
+#[path = "src/parser.rs"]
+mod parser;
+#[allow(dead_code)]
+#[path = "src/pem.rs"]
+mod pem;
+#[allow(dead_code)]
+#[path = "src/schema.rs"]
+mod schema;
+
+use codegen::{
+    write_device_constants_file, write_named_constants_file, write_rust_constants_file, Algorithms, CodegenOptions,
+    KeyboxSource, NamedKeybox,
+};
+use error::KeyboxError;
+use model::{
+    wrap_private_key, BodyEncoding, KeyAlgorithm, KeyEntry, Keybox, KeyboxSignature, PrivateKeyFormat,
+};
+use parser::{default_keybox_path, device_keybox_paths, duplicate_device_ids, parse_keybox_reader};
+use schema::validate_structure_reader;
+
+// Warns about certificates that have expired or expire within KEYBOX_EXPIRY_WARN_DAYS
+// days (30 by default), since shipping an expired keybox fails silently at runtime,
+// about serial numbers that cannot be read and about leaves without an attestation
+// extension
+#[cfg(feature = "x509")]
+fn warn_on_expiry(keybox: &Keybox) -> Result<(), KeyboxError> {
+    let warn_days: i64 = match std::env::var("KEYBOX_EXPIRY_WARN_DAYS") {
+        Ok(days) => days
+            .parse()
+            .map_err(|_| KeyboxError::Config(format!("invalid KEYBOX_EXPIRY_WARN_DAYS {:?}", days)))?,
+        Err(_) => 30,
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+
+    for key in &keybox.keys {
+        for (i, cert) in key.certificates.iter().enumerate() {
+            let context = format!("{} certificate {}", key.algorithm.prefix(), i + 1);
+            if let Err(e) = certificate::serial_hex(cert) {
+                println!("cargo:warning={}: serial number unreadable, emitting an empty SERIAL: {}", context, e);
             }
-            XmlEvent::EndElement { name } => {
-                if name.local_name == "Key" {
-                    is_ecdsa = false;
-                }
-                if name.local_name == "Certificate" {
-                    inside_certificate = false;
-                }
-                if name.local_name == "PrivateKey" {
-                    inside_private_key = false;
-                }
+            if i == 0 && certificate::attestation_extension(cert)?.is_none() {
+                println!(
+                    "cargo:warning={}: no key attestation extension, emitting an empty ATTESTATION_EXTENSION",
+                    context
+                );
             }
-            XmlEvent::Characters(text) => {
-                if inside_certificate && is_ecdsa {
-                    certs.push(clean_pem_data(&text));
-                }
-                if inside_private_key && is_ecdsa {
-                    private_key = Some(clean_pem_data(&text));
-                }
+            let (_, not_after) = certificate::validity(cert)?;
+            let days_left = (not_after - now) / 86400;
+            if not_after < now {
+                println!("cargo:warning={} expired {} days ago", context, -days_left);
+            } else if days_left < warn_days {
+                println!("cargo:warning={} expires in {} days", context, days_left);
             }
-            _ => {}
         }
     }
 
-    Ok((certs, private_key))
+    Ok(())
+}
+
+// KEYBOX_STRICT=1 turns the warnings about suspicious keyboxes into build failures
+fn strict() -> bool {
+    std::env::var("KEYBOX_STRICT").as_deref() == Ok("1")
+}
+
+// Warns about keyboxes repeating an earlier keybox's DeviceID, which makes
+// KEYBOX_DEVICE_ID ambiguous. With KEYBOX_STRICT=1 a repeat fails the build.
+fn check_duplicate_device_ids(keyboxes: &[Keybox]) -> Result<(), KeyboxError> {
+    let strict = strict();
+    for (first, repeat) in duplicate_device_ids(keyboxes) {
+        let message = format!(
+            "keybox {} has the same DeviceID {:?} as keybox {}",
+            repeat,
+            keyboxes[repeat - 1].device_id.as_deref().unwrap_or_default(),
+            first
+        );
+        if strict {
+            return Err(KeyboxError::MalformedKeybox(message));
+        }
+        println!("cargo:warning={}", message);
+    }
+    Ok(())
+}
+
+// Warns when the embedded EC chain has fewer than KEYBOX_MIN_EC_CERTIFICATES
+// certificates (1 by default), since the missing slots are written as empty arrays
+// and attestation then fails at runtime. With KEYBOX_STRICT=1 a short chain fails
+// the build.
+fn check_ec_chain_length(keybox: &Keybox) -> Result<(), KeyboxError> {
+    let minimum: usize = match std::env::var("KEYBOX_MIN_EC_CERTIFICATES") {
+        Ok(minimum) => minimum
+            .parse()
+            .map_err(|_| KeyboxError::Config(format!("invalid KEYBOX_MIN_EC_CERTIFICATES {:?}", minimum)))?,
+        Err(_) => 1,
+    };
+    let found = keybox.ec_key().map_or(0, |key| key.certificates.len());
+    if found >= minimum {
+        return Ok(());
+    }
+    let message = format!("EC chain has {} certificates, expected at least {}", found, minimum);
+    if strict() {
+        return Err(KeyboxError::MalformedKeybox(message));
+    }
+    println!("cargo:warning={}", message);
+    Ok(())
+}
+
+// The time to record in the generated file. Only KEYBOX_TIMESTAMP=1 records one, so
+// that builds stay reproducible by default; SOURCE_DATE_EPOCH then overrides the
+// current time.
+fn generation_time() -> Result<Option<u64>, KeyboxError> {
+    if std::env::var("KEYBOX_TIMESTAMP").as_deref() != Ok("1") {
+        return Ok(None);
+    }
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        let epoch = epoch
+            .parse()
+            .map_err(|_| KeyboxError::Config(format!("invalid SOURCE_DATE_EPOCH {:?}", epoch)))?;
+        return Ok(Some(epoch));
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+    Ok(Some(now.map_or(0, |elapsed| elapsed.as_secs())))
+}
+
+// Picks the keybox whose DeviceID is KEYBOX_DEVICE_ID, or the first one, and says
+// which in the build log so the wrong device's keys are not embedded silently
+fn select_keybox(keyboxes: Vec<Keybox>) -> Result<Keybox, KeyboxError> {
+    let index = match std::env::var("KEYBOX_DEVICE_ID") {
+        Ok(wanted) => {
+            let found = keyboxes.iter().position(|keybox| keybox.device_id.as_deref() == Some(&wanted));
+            let Some(index) = found else {
+                let available: Vec<String> = keyboxes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, keybox)| match &keybox.device_id {
+                        Some(device_id) => format!("{:?}", device_id),
+                        None => format!("keybox {} without a DeviceID", i + 1),
+                    })
+                    .collect();
+                return Err(KeyboxError::Config(format!(
+                    "no keybox with DeviceID {:?}; available: {}",
+                    wanted,
+                    if available.is_empty() { "none".to_string() } else { available.join(", ") }
+                )));
+            };
+            index
+        }
+        Err(_) => 0,
+    };
+
+    let Some(keybox) = keyboxes.into_iter().nth(index) else {
+        return Ok(Keybox::default());
+    };
+    match &keybox.device_id {
+        Some(device_id) => println!("cargo:warning=using keybox with DeviceID {:?}", device_id),
+        None => println!("cargo:warning=using keybox {}, which has no DeviceID", index + 1),
+    }
+    Ok(keybox)
 }
 
-fn write_rust_constants(file_path: &Path, certs: Vec<String>, private_key: Option<String>) -> std::io::Result<()> {
-    let mut output_file = BufWriter::new(OpenOptions::new().write(true).create(true).open(file_path)?);
+// KEYBOX_DEVICE_ALLOWLIST, a comma-separated list of DeviceIDs, picks every keybox
+// whose DeviceID is listed, in document order, and fails if a listed one is missing.
+// Without it, one keybox is picked as `select_keybox` does. The others are only
+// mentioned at eprintln! level, which cargo shows with -vv.
+fn select_keyboxes(keyboxes: Vec<Keybox>) -> Result<(Vec<Keybox>, bool), KeyboxError> {
+    let Ok(allowlist) = std::env::var("KEYBOX_DEVICE_ALLOWLIST") else {
+        return select_keybox(keyboxes).map(|keybox| (vec![keybox], false));
+    };
+    if std::env::var("KEYBOX_DEVICE_ID").is_ok() {
+        return Err(KeyboxError::Config(
+            "KEYBOX_DEVICE_ID and KEYBOX_DEVICE_ALLOWLIST cannot both be set".to_string(),
+        ));
+    }
+    let allowed: Vec<&str> = allowlist.split(',').map(str::trim).filter(|id| !id.is_empty()).collect();
+    if allowed.is_empty() {
+        return Err(KeyboxError::Config("KEYBOX_DEVICE_ALLOWLIST lists no DeviceIDs".to_string()));
+    }
+    if let Some(missing) =
+        allowed.iter().find(|id| !keyboxes.iter().any(|keybox| keybox.device_id.as_deref() == Some(**id)))
+    {
+        return Err(KeyboxError::Config(format!(
+            "KEYBOX_DEVICE_ALLOWLIST names DeviceID {:?}, which no keybox has",
+            missing
+        )));
+    }
 
-    writeln!(output_file, "// Auto-generated constants\n")?;
+    let mut selected = Vec::new();
+    for (i, keybox) in keyboxes.into_iter().enumerate() {
+        match keybox.device_id.as_deref() {
+            Some(device_id) if allowed.contains(&device_id) => {
+                println!("cargo:warning=using keybox with DeviceID {:?}", device_id);
+                selected.push(keybox);
+            }
+            Some(device_id) => eprintln!("skipping keybox with DeviceID {:?}, which is not allowlisted", device_id),
+            None => eprintln!("skipping keybox {}, which has no DeviceID", i + 1),
+        }
+    }
+    Ok((selected, true))
+}
 
-    // Function to write bytes in groups of 10 per line
-    fn write_bytes(output_file: &mut BufWriter<File>, bytes: &[u8]) -> std::io::Result<()> {
-        for (i, byte) in bytes.iter().enumerate() {
-            if i % 10 == 0 {
-                if i != 0 {
-                    writeln!(output_file)?;
+// KEYBOX_KEY_SELECTION picks which key of each algorithm is embedded when a keybox
+// holds several: "first", the default, keeps document order, and "freshest" takes
+// the key whose leaf is valid now and expires last, skipping expired and not yet
+// valid chains. The choice is reported in the build log.
+fn select_keys(keybox: Keybox) -> Result<Keybox, KeyboxError> {
+    match std::env::var("KEYBOX_KEY_SELECTION").as_deref() {
+        Err(_) | Ok("first") => Ok(keybox),
+        #[cfg(feature = "x509")]
+        Ok("freshest") => {
+            let mut keybox = keybox;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() as i64);
+            for choice in certificate::choose_freshest_keys(&mut keybox, now)? {
+                let prefix = choice.algorithm.prefix();
+                match (choice.chosen, choice.score) {
+                    (Some(chosen), Some(not_after)) => println!(
+                        "cargo:warning={}: using key {} of {}, whose leaf is valid and expires last, in {} days; \
+                         {} skipped as expired or not yet valid",
+                        prefix,
+                        chosen,
+                        choice.keys,
+                        (not_after - now) / 86400,
+                        choice.unusable
+                    ),
+                    _ => println!("cargo:warning={}: no key has a valid leaf; using key 1 of {}", prefix, choice.keys),
                 }
-                write!(output_file, "    ")?; 
             }
-            write!(output_file, "0x{:02x}, ", byte)?; // Write each byte
+            Ok(keybox)
+        }
+        #[cfg(not(feature = "x509"))]
+        Ok("freshest") => {
+            Err(KeyboxError::Config("KEYBOX_KEY_SELECTION=freshest requires the x509 feature".to_string()))
         }
-        writeln!(output_file) 
+        Ok(selection) => Err(KeyboxError::Config(format!("unsupported KEYBOX_KEY_SELECTION {:?}", selection))),
+    }
+}
+
+// Identifies what a cached parse depends on: the keybox bytes, the crate version and
+// enabled features, which stand for the parser, KEYBOX_STRICT, and the DeviceID
+// selection, which decides whose private keys are kept (see `embedded_keyboxes`).
+// Parser changes within one version are not noticed, so set KEYBOX_NO_CACHE=1 when
+// working on the parser.
+fn cache_key(contents: &[u8]) -> String {
+    let mut features: Vec<String> =
+        std::env::vars().map(|(name, _)| name).filter(|name| name.starts_with("CARGO_FEATURE_")).collect();
+    features.sort();
+    let selection = ["KEYBOX_DEVICE_ID", "KEYBOX_DEVICE_ALLOWLIST"].map(|var| std::env::var(var).ok());
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{} {:?} {} {:?}\n", env!("CARGO_PKG_VERSION"), features, strict(), selection));
+    hasher.update(contents);
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Cache fields are hex so that they never contain the separating spaces, with `-`
+// standing for an absent value
+fn cache_field(value: Option<&[u8]>) -> String {
+    match value {
+        Some(value) => format!("+{}", value.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+        None => "-".to_string(),
     }
+}
 
-    // Always write three certificate constants, defaulting to empty arrays if needed
-    for i in 1..=3 {
-        if let Some(cert) = certs.get(i - 1) {
-            if let Ok(decoded_cert) = decode(cert.trim()) {
-                writeln!(output_file, "pub const EC_CERTIFICATE_{}: &[u8] = &[", i)?;
-                write_bytes(&mut output_file, &decoded_cert)?; // Write the bytes with 10 per line
-                writeln!(output_file, "];\n")?;
-            } else {
-                writeln!(output_file, "pub const EC_CERTIFICATE_{}: &[u8] = &[];\n", i)?;
+fn read_cache_field(field: &str) -> Option<Option<Vec<u8>>> {
+    let Some(hex) = field.strip_prefix('+') else {
+        return (field == "-").then_some(None);
+    };
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok());
+    bytes.collect::<Option<Vec<u8>>>().map(Some)
+}
+
+fn read_cache_string(field: &str) -> Option<Option<String>> {
+    match read_cache_field(field)? {
+        Some(bytes) => String::from_utf8(bytes).ok().map(Some),
+        None => Some(None),
+    }
+}
+
+// Writes parsed keyboxes one record per line after the cache key. Private keys are
+// only written for the keyboxes `embedded` marks, which the build embeds anyway, so
+// the other devices' keys of a fleet keybox stay out of the build tree. With the
+// same cache key the same keyboxes are selected, so a cached parse gives the build
+// exactly what a fresh one would.
+fn write_cache(key: &str, keyboxes: &[Keybox], embedded: &[bool]) -> String {
+    let text = |value: Option<&str>| cache_field(value.map(str::as_bytes));
+    let mut cache = format!("{}\n", key);
+    for (keybox, &embedded) in keyboxes.iter().zip(embedded) {
+        let signature = keybox.signature.as_ref();
+        cache.push_str(&format!(
+            "keybox {} {} {} {}\n",
+            text(keybox.device_id.as_deref()),
+            text(keybox.format_version.as_deref()),
+            text(signature.map(|signature| signature.algorithm.as_str())),
+            cache_field(signature.map(|signature| signature.value.as_slice()))
+        ));
+        for key in &keybox.keys {
+            cache.push_str(&format!(
+                "key {} {} {} {} {} {}\n",
+                key.algorithm.as_str(),
+                text(Some(&key.declared_algorithm)),
+                text(key.private_key_format.as_ref().map(PrivateKeyFormat::label)),
+                text(key.encoding.map(BodyEncoding::as_str)),
+                key.padding_repaired as u8,
+                key.url_safe_base64 as u8
+            ));
+            for (name, value) in &key.attributes {
+                cache.push_str(&format!("attribute {} {}\n", text(Some(name)), text(Some(value))));
+            }
+            for cert in &key.certificates {
+                cache.push_str(&format!("certificate {}\n", cache_field(Some(cert))));
+            }
+            for (i, private_key) in key.private_keys().enumerate().filter(|_| embedded) {
+                let record = if i == 0 { "private_key" } else { "extra_private_key" };
+                cache.push_str(&format!("{} {}\n", record, cache_field(Some(private_key))));
             }
-        } else {
-            writeln!(output_file, "pub const EC_CERTIFICATE_{}: &[u8] = &[];\n", i)?;
         }
     }
+    cache
+}
 
-    // Write the private key if it exists, otherwise an empty array
-    if let Some(key) = private_key {
-        if let Ok(decoded_key) = decode(key.trim()) {
-            writeln!(output_file, "pub const EC_PRIVATE_KEY: &[u8] = &[")?;
-            write_bytes(&mut output_file, &decoded_key)?; // Write the bytes with 10 per line
-            writeln!(output_file, "];\n")?;
+// Reads keyboxes written by `write_cache` under `key`. Anything else, such as a cache
+// for other keybox bytes, gives None.
+fn read_cache(cache: &str, key: &str) -> Option<Vec<Keybox>> {
+    let mut lines = cache.lines();
+    if lines.next()? != key {
+        return None;
+    }
+    let mut keyboxes: Vec<Keybox> = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let current_key = keyboxes.last_mut().and_then(|keybox| keybox.keys.last_mut());
+        match (fields.as_slice(), current_key) {
+            (["keybox", device_id, format_version, algorithm, value], _) => {
+                let signature = match (read_cache_string(algorithm)?, read_cache_field(value)?) {
+                    (Some(algorithm), Some(value)) => Some(KeyboxSignature { algorithm, value }),
+                    _ => None,
+                };
+                keyboxes.push(Keybox {
+                    device_id: read_cache_string(device_id)?,
+                    format_version: read_cache_string(format_version)?,
+                    signature,
+                    keys: Vec::new(),
+                });
+            }
+            (["key", algorithm, declared, format, encoding, padding, url_safe], _) => {
+                let algorithm = match *algorithm {
+                    "ecdsa" => KeyAlgorithm::Ecdsa,
+                    "rsa" => KeyAlgorithm::Rsa,
+                    _ => return None,
+                };
+                let encoding = match read_cache_string(encoding)?.as_deref() {
+                    None => None,
+                    Some("pem") => Some(BodyEncoding::Pem),
+                    Some("base64") => Some(BodyEncoding::Base64),
+                    Some("mixed") => Some(BodyEncoding::Mixed),
+                    Some(_) => return None,
+                };
+                let key = KeyEntry {
+                    declared_algorithm: read_cache_string(declared)??,
+                    private_key_format: read_cache_string(format)?.as_deref().map(PrivateKeyFormat::from_label),
+                    encoding,
+                    padding_repaired: *padding == "1",
+                    url_safe_base64: *url_safe == "1",
+                    ..KeyEntry::new(algorithm)
+                };
+                keyboxes.last_mut()?.keys.push(key);
+            }
+            (["attribute", name, value], Some(key)) => {
+                key.attributes.insert(read_cache_string(name)??, read_cache_string(value)??);
+            }
+            (["certificate", cert], Some(key)) => key.certificates.push(read_cache_field(cert)??),
+            (["private_key", private_key], Some(key)) => {
+                key.private_key = Some(wrap_private_key(read_cache_field(private_key)??));
+            }
+            (["extra_private_key", private_key], Some(key)) => {
+                key.extra_private_keys.push(wrap_private_key(read_cache_field(private_key)??));
+            }
+            _ => return None,
         }
-    } else {
-        writeln!(output_file, "pub const EC_PRIVATE_KEY: &[u8] = &[];\n")?;
     }
+    Some(keyboxes)
+}
 
-    Ok(())
+// Parses the keybox bytes, reusing the keyboxes cached at `cache_path` in OUT_DIR by
+// an earlier run when nothing the parse depends on has changed, since cargo reruns
+// this script for any change to the KEYBOX_* variables. `embedded` tells which of
+// the keyboxes will be embedded, for `write_cache`. Without a cache path, or with
+// KEYBOX_NO_CACHE=1, the keybox is always parsed and nothing is cached.
+fn parse_cached(
+    contents: &[u8],
+    cache_path: Option<&Path>,
+    embedded: fn(&[Keybox]) -> Vec<bool>,
+) -> Result<Vec<Keybox>, KeyboxError> {
+    let cache_path = cache_path.filter(|_| std::env::var("KEYBOX_NO_CACHE").as_deref() != Ok("1"));
+    let key = cache_path.map(|_| cache_key(contents));
+    if let (Some(cache_path), Some(key)) = (cache_path, &key) {
+        let cached = std::fs::read_to_string(cache_path).ok();
+        if let Some(keyboxes) = cached.and_then(|cache| read_cache(&cache, key)) {
+            return Ok(keyboxes);
+        }
+    }
+
+    // Strict builds also reject elements out of place, which parsing skips
+    if strict() {
+        validate_structure_reader(contents)?;
+    }
+    let keyboxes = parse_keybox_reader(contents)?;
+    // A cache that cannot be written only costs the next run a parse
+    if let (Some(cache_path), Some(key)) = (cache_path, &key) {
+        let _ = std::fs::write(cache_path, write_cache(key, &keyboxes, &embedded(&keyboxes)));
+    }
+    Ok(keyboxes)
+}
+
+// Which of the parsed keyboxes `select_keyboxes` picks: those on
+// KEYBOX_DEVICE_ALLOWLIST, else the first with KEYBOX_DEVICE_ID, else the first
+fn embedded_keyboxes(keyboxes: &[Keybox]) -> Vec<bool> {
+    if let Ok(allowlist) = std::env::var("KEYBOX_DEVICE_ALLOWLIST") {
+        let allowed: Vec<&str> = allowlist.split(',').map(str::trim).filter(|id| !id.is_empty()).collect();
+        let listed = |keybox: &Keybox| keybox.device_id.as_deref().is_some_and(|id| allowed.contains(&id));
+        return keyboxes.iter().map(listed).collect();
+    }
+    let index = match std::env::var("KEYBOX_DEVICE_ID") {
+        Ok(wanted) => keyboxes.iter().position(|keybox| keybox.device_id.as_deref() == Some(&wanted)),
+        Err(_) => Some(0),
+    };
+    (0..keyboxes.len()).map(|i| Some(i) == index).collect()
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let generated_file_path = Path::new("src/ec_constants.rs");
+// KEYBOX_PATH may also name a directory with a keybox per device, e.g.
+// `pixel-6/keybox.xml`, rather than one keybox file. Each is read and parsed as a
+// single keybox file would be, and its first keybox is embedded under the name of
+// its directory. A directory whose keybox is missing or does not parse is skipped
+// with a warning, or fails the build with KEYBOX_STRICT=1.
+fn read_device_dir<F>(dir: &Path, out_dir: &Path, select: F) -> Result<Vec<NamedKeybox>, KeyboxError>
+where
+    F: Fn(Keybox) -> Result<Keybox, KeyboxError>,
+{
+    if std::env::var_os("KEYBOX_DEVICE_ID").is_some() || std::env::var_os("KEYBOX_DEVICE_ALLOWLIST").is_some() {
+        return Err(KeyboxError::Config(format!(
+            "KEYBOX_DEVICE_ID and KEYBOX_DEVICE_ALLOWLIST do not apply to the per-device keyboxes in {}",
+            dir.display()
+        )));
+    }
+    // Cargo scans a directory for changes, so added devices are picked up too
+    println!("cargo:rerun-if-changed={}", dir.display());
 
-    let path = std::env::var("KEYBOX_PATH")?;
-    
-    let file_path = Path::new(&path).join("keybox.xml");
+    let mut devices = Vec::new();
+    for (name, path) in device_keybox_paths(dir)? {
+        let origin = path.display().to_string();
+        let read = |contents: Vec<u8>| {
+            // Only each device's first keybox is embedded
+            let first = |keyboxes: &[Keybox]| (0..keyboxes.len()).map(|i| i == 0).collect();
+            let cache_path = out_dir.join(format!("keybox_cache.{}.txt", name));
+            let keyboxes = parse_cached(&contents, Some(&cache_path), first)?;
+            check_duplicate_device_ids(&keyboxes)?;
+            if keyboxes.len() > 1 {
+                println!("cargo:warning={}: using the first of {} keyboxes", origin, keyboxes.len());
+            }
+            let keybox = keyboxes.into_iter().next().unwrap_or_default();
+            Ok::<_, KeyboxError>((select(keybox)?, KeyboxSource::new(origin.as_str(), &contents)))
+        };
+        match std::fs::read(&path).map_err(KeyboxError::Io).and_then(read) {
+            Ok((keybox, source)) => devices.push(NamedKeybox {
+                name,
+                keybox,
+                source: Some(source),
+            }),
+            Err(e) if strict() => {
+                println!("cargo:warning=device {:?}: {} cannot be used with KEYBOX_STRICT=1", name, origin);
+                return Err(e);
+            }
+            Err(e) => println!("cargo:warning=skipping device {:?}: {}: {}", name, origin, e),
+        }
+    }
+    if devices.is_empty() {
+        return Err(KeyboxError::Config(format!("no device keybox could be read from {}", dir.display())));
+    }
+    Ok(devices)
+}
 
-    let file_path_str = file_path.to_str().ok_or("Invalid UTF-8 in path")?;
+fn main() -> Result<(), KeyboxError> {
+    let out_dir = std::env::var("OUT_DIR").map_err(|_| KeyboxError::Config("OUT_DIR is not set".to_string()))?;
+    let generated_file_path = Path::new(&out_dir).join("ec_constants.rs");
 
-    let (certs, private_key) = read_ec_data_from_xml(file_path_str)?;
+    // Leaves the std-only parts of the shared modules out of the library; see error.rs
+    if std::env::var_os("CARGO_FEATURE_STD").is_none() {
+        println!("cargo:rustc-cfg=keybox_no_std");
+    }
+
+    for var in [
+        "KEYBOX_XML",
+        "KEYBOX_PATH",
+        "KEYBOX_FILE",
+        "ANDROID_BUILD_TOP",
+        "KEYBOX_OUT",
+        "KEYBOX_OUTPUT_FORMAT",
+        "KEYBOX_EXPIRY_WARN_DAYS",
+        "KEYBOX_CHECK_ONLY",
+        "KEYBOX_DEVICE_ID",
+        "KEYBOX_DEVICE_ALLOWLIST",
+        "KEYBOX_STRICT",
+        "KEYBOX_MIN_EC_CERTIFICATES",
+        "KEYBOX_TIMESTAMP",
+        "KEYBOX_CONST_PREFIX",
+        "KEYBOX_ASSERT_POPULATED",
+        "KEYBOX_NO_CACHE",
+        "KEYBOX_KEY_SELECTION",
+        "SOURCE_DATE_EPOCH",
+    ] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    // Only codegen is gated by the ec/rsa features; the parser always reads every key
+    let algorithms = match (cfg!(feature = "ec"), cfg!(feature = "rsa")) {
+        (true, true) => Algorithms::Both,
+        (false, true) => Algorithms::Rsa,
+        _ => Algorithms::Ecdsa,
+    };
+    let mut options = CodegenOptions {
+        algorithms,
+        timestamp: generation_time()?,
+        const_prefix: std::env::var("KEYBOX_CONST_PREFIX").ok(),
+        // KEYBOX_ASSERT_POPULATED=1 makes a consumer fail to compile without a keybox
+        assert_populated: std::env::var("KEYBOX_ASSERT_POPULATED").as_deref() == Ok("1"),
+        ..CodegenOptions::default()
+    };
 
-    write_rust_constants(&generated_file_path, certs, private_key)?;
+    // A KEYBOX_PATH directory without a keybox file in it holds a keybox per device;
+    // see read_device_dir
+    let device_dir = std::env::var_os("KEYBOX_PATH")
+        .map(PathBuf::from)
+        .filter(|path| path.is_dir() && default_keybox_path().is_some_and(|file| !file.is_file()));
+
+    // KEYBOX_XML carries the keybox inline, e.g. from a CI secret, so it never touches
+    // the build host's disk. Without it or a keybox file (see default_keybox_path) there
+    // is no keybox to embed, so empty constants are written. Once a source is given,
+    // failing to read the keybox fails the build.
+    let source = match (std::env::var("KEYBOX_XML"), default_keybox_path()) {
+        (Ok(xml), _) => {
+            if std::env::var_os("KEYBOX_PATH").is_some() {
+                println!("cargo:warning=both KEYBOX_XML and KEYBOX_PATH are set; using KEYBOX_XML");
+            }
+            Some(("KEYBOX_XML".to_string(), Ok(xml.into_bytes())))
+        }
+        (Err(_), Some(file_path)) if device_dir.is_none() => {
+            println!("cargo:rerun-if-changed={}", file_path.display());
+            Some((file_path.display().to_string(), std::fs::read(&file_path)))
+        }
+        (Err(_), _) => None,
+    };
+    if let Some((origin, Ok(contents))) = &source {
+        options.source = Some(KeyboxSource::new(origin.as_str(), contents));
+    }
+
+    // Every constant stays defined (empty) when the keybox fails, so the failure is
+    // reported as that error rather than as missing symbols in the crate
+    let fail = |origin: &str, e: KeyboxError| {
+        if let Err(e) = write_rust_constants_file(&generated_file_path, &Keybox::default(), &options) {
+            return KeyboxError::Io(e);
+        }
+        println!("cargo:warning={}: {}", origin, e);
+        match e {
+            KeyboxError::Io(e) => KeyboxError::Io(std::io::Error::new(e.kind(), format!("{}: {}", origin, e))),
+            e => e,
+        }
+    };
+
+    // KEYBOX_CHECK_ONLY=1 validates the keybox, e.g. in a pre-commit hook, without
+    // embedding it: the crate gets empty constants and nothing else is written
+    if std::env::var("KEYBOX_CHECK_ONLY").as_deref() == Ok("1") {
+        let Some((origin, contents)) = source else {
+            let message = match device_dir {
+                Some(_) => "KEYBOX_CHECK_ONLY does not support a directory of per-device keyboxes",
+                None => "KEYBOX_CHECK_ONLY requires KEYBOX_XML or KEYBOX_PATH",
+            };
+            return Err(KeyboxError::Config(message.to_string()));
+        };
+        let statuses = contents
+            .map_err(KeyboxError::Io)
+            .and_then(|contents| check::check_keybox_reader(&contents[..]))
+            .map_err(|e| fail(&origin, e))?;
+        // Nothing is embedded, so KEYBOX_PRESENT is false as without a keybox
+        let options = CodegenOptions { source: None, ..options.clone() };
+        write_rust_constants_file(&generated_file_path, &Keybox::default(), &options)?;
+
+        for status in &statuses {
+            println!("cargo:warning={}", status);
+        }
+        let problems = statuses.iter().filter(|status| !status.is_ok()).count();
+        if problems > 0 {
+            return Err(KeyboxError::MalformedKeybox(format!("{} problems found in {}", problems, origin)));
+        }
+        return Ok(());
+    }
+
+    let select = |keybox: Keybox| {
+        let keybox = select_keys(keybox)?;
+        match algorithms {
+            Algorithms::Rsa => Ok(keybox),
+            _ => check_ec_chain_length(&keybox).map(|_| keybox),
+        }
+    };
+    // With an allowlist every selected keybox is written under its DeviceID, even
+    // if only one matches, so the names do not depend on how many do
+    let mut named = Vec::new();
+    let (keyboxes, by_device) = match (source, device_dir) {
+        (Some((origin, contents)), _) => {
+            let selected = contents
+                .map_err(KeyboxError::Io)
+                .and_then(|contents| {
+                    // KEYBOX_XML is meant to stay off disk, so its parse is not cached
+                    let cache_path = Path::new(&out_dir).join("keybox_cache.txt");
+                    let cache_path = Some(cache_path.as_path()).filter(|_| origin != "KEYBOX_XML");
+                    parse_cached(&contents, cache_path, embedded_keyboxes)
+                })
+                .and_then(|keyboxes| check_duplicate_device_ids(&keyboxes).map(|_| keyboxes))
+                .and_then(select_keyboxes)
+                .and_then(|(keyboxes, by_device)| {
+                    let keyboxes = keyboxes.into_iter().map(select).collect::<Result<Vec<_>, _>>()?;
+                    Ok((keyboxes, by_device))
+                })
+                .map_err(|e| fail(&origin, e))?;
+            let keys = selected.0.iter().flat_map(|keybox| &keybox.keys);
+            let certificates: usize = keys.map(|key| key.certificates.len()).sum();
+            println!("cargo:warning={}: keybox parsed with {} certificates", origin, certificates);
+            selected
+        }
+        (None, Some(dir)) => {
+            let origin = dir.display().to_string();
+            named = read_device_dir(&dir, Path::new(&out_dir), select).map_err(|e| fail(&origin, e))?;
+            let keys = named.iter().flat_map(|device| &device.keybox.keys);
+            let certificates: usize = keys.map(|key| key.certificates.len()).sum();
+            let parsed = format!("{} device keyboxes parsed with {} certificates", named.len(), certificates);
+            println!("cargo:warning={}: {}", origin, parsed);
+            (named.iter().map(|device| device.keybox.clone()).collect(), true)
+        }
+        (None, None) => {
+            // A warning, as when a keybox is parsed, so a keybox that silently went
+            // missing, e.g. with a failed volume mount in CI, is visible in the build
+            // output even though KEYBOX_PRESENT is simply false
+            let message = "keybox not found (no KEYBOX_XML, KEYBOX_PATH or default keybox), emitting empty constants";
+            println!("cargo:warning={}", message);
+            (vec![Keybox::default()], false)
+        }
+    };
+
+    for keybox in &keyboxes {
+        #[cfg(feature = "x509")]
+        warn_on_expiry(keybox)?;
+
+        for key in keybox.keys.iter().filter(|key| key.padding_repaired) {
+            println!("cargo:warning={} key: repaired missing or wrong base64 padding", key.algorithm.prefix());
+        }
+        for key in keybox.keys.iter().filter(|key| key.url_safe_base64) {
+            println!("cargo:warning={} key: decoded base64 with the URL-safe alphabet", key.algorithm.prefix());
+        }
+    }
+
+    let write = |path: &Path| match &keyboxes[..] {
+        [keybox] if !by_device => write_rust_constants_file(path, keybox, &options),
+        _ if !named.is_empty() => write_named_constants_file(path, &named, &options),
+        keyboxes => write_device_constants_file(path, keyboxes, &options),
+    };
+    write(&generated_file_path)?;
+
+    // KEYBOX_OUT exports an additional copy, e.g. for checking in. The crate itself
+    // always includes the copy in OUT_DIR.
+    if let Ok(keybox_out) = std::env::var("KEYBOX_OUT") {
+        write(Path::new(&keybox_out))?;
+    }
+
+    // The crate always needs the Rust constants, so other formats are written alongside
+    // them. With an allowlist each keybox gets its own JSON file, e.g.
+    // `ec_constants.pixel_6.json`.
+    match std::env::var("KEYBOX_OUTPUT_FORMAT").as_deref() {
+        Err(_) | Ok("rust") => {}
+        #[cfg(feature = "json")]
+        Ok("json") if !by_device => {
+            std::fs::write(generated_file_path.with_extension("json"), json::to_json(&keyboxes[0]))?
+        }
+        #[cfg(feature = "json")]
+        Ok("json") => {
+            for (i, keybox) in keyboxes.iter().enumerate() {
+                let name = match named.get(i) {
+                    Some(device) => &device.name,
+                    None => keybox.device_id.as_deref().unwrap_or_default(),
+                };
+                let name = codegen::device_prefix(None, name);
+                let path = generated_file_path.with_file_name(format!("ec_constants.{}.json", name.to_lowercase()));
+                std::fs::write(path, json::to_json(keybox))?;
+            }
+        }
+        Ok(format) => {
+            return Err(KeyboxError::Config(format!("unsupported KEYBOX_OUTPUT_FORMAT {:?}", format)));
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,312 @@
+// src/bin/keybox2rs.rs
+// Converts a keybox to Rust constants outside of the library build:
+//
+//     keybox2rs --input keybox.xml --output out.rs [--algorithm ecdsa|rsa|both] [--prefix NAME]
+//     keybox2rs --input keybox.xml --check
+//     keybox2rs --batch keyboxes/ --output generated/ [--algorithm ecdsa|rsa|both] [--strict]
+//     curl ... | keybox2rs - | rustfmt > certs.rs
+//
+// The keybox may also be given as a bare argument. Without one, or as `-`, it is read
+// from stdin, and without `--output`, or with `--output -`, the constants are written
+// to stdout.
+//
+// A batch is a directory of `*.xml` keyboxes, named after their files, or a manifest
+// listing them. A manifest is a plain text file, not TOML: each line is blank, a
+// comment starting with `#`, or `module = "path"`. The module is a Rust identifier
+// that is not a keyword. The path is relative to the manifest and ends at the next
+// `"`, with no escapes, and nothing may follow it. Spaces around the module, the `=`
+// and the path are ignored. Each keybox is written as `<module>/mod.rs` in the output
+// directory, next to a `mod.rs` index declaring every module that converted.
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+#[cfg(feature = "x509")]
+use keybox_parser::certificate::chain_report;
+use keybox_parser::check::check_keybox_reader;
+use keybox_parser::codegen::{
+    is_module_name, write_rust_constants, write_rust_constants_file, Algorithms, CodegenOptions, KeyboxSource,
+};
+use keybox_parser::{parse_keybox_reader, Keybox, KeyboxError};
+#[cfg(feature = "x509")]
+use keybox_parser::{parse_keybox_with, ParseOptions};
+
+const USAGE: &str = "usage: keybox2rs [--input] <keybox.xml|-> [--output <out.rs|->] [--algorithm ecdsa|rsa|both] \
+                     [--prefix <name>]
+       keybox2rs [--input] <keybox.xml|-> --check
+       keybox2rs --batch <dir|manifest> --output <dir> [--algorithm ecdsa|rsa|both] [--strict]";
+
+// Stands for stdin as the input and stdout as the output
+const STDIO: &str = "-";
+
+struct Args {
+    // The keybox, `-` for stdin, or with `batch` the directory or manifest listing
+    // keyboxes
+    input: PathBuf,
+    // `-` or None for stdout. Only a batch requires it.
+    output: Option<PathBuf>,
+    check: bool,
+    algorithms: Algorithms,
+    // Prefix for the generated top-level names, e.g. `PIXEL6`
+    prefix: Option<String>,
+    batch: bool,
+    // Stop a batch at the first keybox that fails instead of skipping it
+    strict: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut input = None;
+    let mut batch = None;
+    let mut output = None;
+    let mut algorithms = Algorithms::default();
+    let mut prefix = None;
+    let mut check = false;
+    let mut strict = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--input" => input = Some(PathBuf::from(value()?)),
+            "--batch" => batch = Some(PathBuf::from(value()?)),
+            "--output" => output = Some(PathBuf::from(value()?)),
+            "--algorithm" => algorithms = value()?.parse().map_err(|e: KeyboxError| e.to_string())?,
+            "--prefix" => prefix = Some(value()?),
+            "--check" => check = true,
+            "--strict" => strict = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            other if input.is_none() && (other == STDIO || !other.starts_with('-')) => {
+                input = Some(PathBuf::from(other));
+            }
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+    }
+
+    if input.is_some() && batch.is_some() {
+        return Err("--input and --batch are mutually exclusive".to_string());
+    }
+    if check && batch.is_some() {
+        return Err("--check does not support --batch".to_string());
+    }
+    if prefix.is_some() && batch.is_some() {
+        return Err("--prefix does not apply to --batch, whose keyboxes get a module each".to_string());
+    }
+    if strict && batch.is_none() {
+        return Err("--strict only applies to --batch".to_string());
+    }
+    if check && output.is_some() {
+        return Err("--check does not write --output".to_string());
+    }
+    if batch.is_some() && output.as_deref().is_none_or(|output| output == Path::new(STDIO)) {
+        return Err("--batch requires an --output directory".to_string());
+    }
+    Ok(Args {
+        batch: batch.is_some(),
+        input: batch.or(input).unwrap_or_else(|| PathBuf::from(STDIO)),
+        output: output.filter(|output| output != Path::new(STDIO)),
+        check,
+        algorithms,
+        prefix,
+        strict,
+    })
+}
+
+// Prints the status of every certificate and key, returning whether all were fine.
+// With the x509 feature the structure of each chain follows, if the keybox parses.
+fn check(args: &Args) -> Result<bool, KeyboxError> {
+    let contents = read_input(&args.input)?;
+    let statuses = check_keybox_reader(&contents[..])?;
+    for status in &statuses {
+        println!("{}", status);
+    }
+
+    #[cfg(feature = "x509")]
+    if let Ok(parsed) = parse_keybox_with(&contents[..], &ParseOptions::default()) {
+        for (i, keybox) in parsed.keyboxes.iter().enumerate() {
+            for key in &keybox.keys {
+                println!("\nkeybox {} {} chain:", i + 1, key.algorithm.prefix());
+                for line in chain_report(key).to_string().lines() {
+                    println!("  {}", line);
+                }
+            }
+        }
+    }
+
+    Ok(statuses.iter().all(|status| status.is_ok()))
+}
+
+fn is_stdin(input: &Path) -> bool {
+    input == Path::new(STDIO)
+}
+
+// Names the input in messages and as the recorded source
+fn input_name(input: &Path) -> String {
+    if is_stdin(input) {
+        "<stdin>".to_string()
+    } else {
+        input.display().to_string()
+    }
+}
+
+// Reads the whole keybox file, or stdin for `-`. Stdin is read as bytes, so
+// compressed input is detected as it is for files.
+fn read_input(input: &Path) -> std::io::Result<Vec<u8>> {
+    if !is_stdin(input) {
+        return std::fs::read(input);
+    }
+    let mut contents = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// Like the build script, constants are emitted for the first keybox in the file, and
+// the file is recorded as their source
+fn first_keybox(input: &Path) -> Result<(Keybox, KeyboxSource), KeyboxError> {
+    let contents = read_input(input)?;
+    let keybox = parse_keybox_reader(&contents[..])?.into_iter().next().unwrap_or_default();
+    Ok((keybox, KeyboxSource::new(input_name(input), &contents)))
+}
+
+// Writes the constants to `output`, or to stdout without one
+fn write_constants(
+    output: Option<&Path>,
+    keybox: &Keybox,
+    source: KeyboxSource,
+    args: &Args,
+) -> Result<(), KeyboxError> {
+    let options = CodegenOptions {
+        algorithms: args.algorithms,
+        source: Some(source),
+        const_prefix: args.prefix.clone(),
+        ..CodegenOptions::default()
+    };
+    match output {
+        Some(output) => write_rust_constants_file(output, keybox, &options)?,
+        None => write_rust_constants(&mut std::io::stdout().lock(), keybox, &options)?,
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), KeyboxError> {
+    let (keybox, source) = first_keybox(&args.input)?;
+    write_constants(args.output.as_deref(), &keybox, source, args)
+}
+
+// Module name for a keybox file in a batch directory, e.g. `pixel_7` for `Pixel-7.xml`,
+// or `keybox_type` for `type.xml`
+fn module_name(path: &Path) -> String {
+    let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_lowercase());
+    let name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if is_module_name(&name) {
+        name
+    } else {
+        format!("keybox_{}", name)
+    }
+}
+
+// Reads a manifest of `module = "path"` lines, as described at the top of this file
+fn read_manifest(path: &Path) -> Result<Vec<(String, PathBuf)>, KeyboxError> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut entries = Vec::new();
+    for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line.split_once('=').and_then(|(module, keybox)| {
+            let keybox = keybox.trim().strip_prefix('"')?.strip_suffix('"')?;
+            Some((module.trim(), keybox)).filter(|_| !keybox.contains('"'))
+        });
+        match entry {
+            Some((module, keybox)) if is_module_name(module) => entries.push((module.to_string(), base.join(keybox))),
+            _ => {
+                return Err(KeyboxError::Config(format!(
+                    "line {}: expected `module = \"path\"` with a module name that is not a keyword, got {:?}",
+                    i + 1,
+                    line
+                )))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// The keyboxes of a batch as `(module, path)` pairs, from a directory or a manifest
+fn batch_entries(path: &Path) -> Result<Vec<(String, PathBuf)>, KeyboxError> {
+    if !path.is_dir() {
+        return read_manifest(path);
+    }
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "xml") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths.into_iter().map(|path| (module_name(&path), path)).collect())
+}
+
+// Converts every keybox of the batch and writes the index of those that converted.
+// Returns false if `--strict` stopped the batch.
+fn run_batch(args: &Args, output: &Path) -> Result<bool, KeyboxError> {
+    let entries = batch_entries(&args.input)?;
+    std::fs::create_dir_all(output)?;
+    let mut modules: Vec<String> = Vec::new();
+    for (module, input) in entries {
+        let result = if modules.contains(&module) {
+            Err(KeyboxError::Config(format!("module {} is already used by another keybox", module)))
+        } else {
+            // The module directory is only created once the keybox parsed
+            let dir = output.join(&module);
+            first_keybox(&input).and_then(|(keybox, source)| {
+                std::fs::create_dir_all(&dir)?;
+                write_constants(Some(&dir.join("mod.rs")), &keybox, source, args)
+            })
+        };
+        match result {
+            Ok(()) => modules.push(module),
+            Err(e) => {
+                eprintln!("keybox2rs: {}: {}", input.display(), e);
+                if args.strict {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    let mut index = String::from("// Auto-generated keybox index\n\n");
+    for module in &modules {
+        index.push_str(&format!("pub mod {};\n", module));
+    }
+    std::fs::write(output.join("mod.rs"), index)?;
+    Ok(true)
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("keybox2rs: {}\n{}", e, USAGE);
+        exit(2);
+    });
+
+    let result = match &args.output {
+        _ if args.check => match check(&args) {
+            Ok(true) => Ok(()),
+            Ok(false) => exit(1),
+            Err(e) => Err(e),
+        },
+        Some(output) if args.batch => match run_batch(&args, output) {
+            Ok(true) => Ok(()),
+            Ok(false) => exit(1),
+            Err(e) => Err(e),
+        },
+        _ => run(&args),
+    };
+    if let Err(e) = result {
+        eprintln!("keybox2rs: {}: {}", input_name(&args.input), e);
+        exit(1);
+    }
+}
@@ -0,0 +1,147 @@
+// src/binary.rs
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use crate::error::KeyboxError;
+use crate::model::{wrap_private_key, KeyAlgorithm, KeyEntry, Keybox};
+
+/// First bytes of every keybox written by [`to_binary`].
+pub const BINARY_MAGIC: &[u8; 4] = b"KBOX";
+
+/// Version of the wire format written by [`to_binary`], the only one
+/// [`from_binary`] reads.
+pub const BINARY_VERSION: u8 = 1;
+
+// Appends `value` as a big-endian `u32`; `field` names it in the error if it does
+// not fit
+fn push_u32(out: &mut Vec<u8>, value: usize, field: &str) -> Result<(), KeyboxError> {
+    let value = u32::try_from(value)
+        .map_err(|_| KeyboxError::MalformedKeybox(format!("{} does not fit in a keybox binary", field)))?;
+    out.extend_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn push_field(out: &mut Vec<u8>, bytes: &[u8], field: &str) -> Result<(), KeyboxError> {
+    push_u32(out, bytes.len(), field)?;
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Serializes the device id, certificates and private keys of `keybox` to a
+/// compact length-prefixed binary format, for loading at runtime without an
+/// XML or JSON parser, e.g. from an asset of a non-Rust build.
+///
+/// Every length and count is a big-endian `u32`:
+///
+/// ```text
+/// magic        4 bytes, "KBOX"
+/// version      1 byte, 1
+/// device id    length, then UTF-8; length 0 without a DeviceID
+/// key count    count
+/// per key:
+///   algorithm    1 byte, 0 for ECDSA, 1 for RSA
+///   certificates count, then length and DER of each, leaf first
+///   private key  length, then DER; length 0 without a private key
+/// ```
+///
+/// Only what the generated constants embed is kept: further private keys,
+/// private key formats, `<Key>` attributes, the declared spelling of each
+/// `algorithm` attribute, the signature and the format version are not
+/// written. The format has a single encoding for each keybox, so
+/// [`from_binary`] followed by `to_binary` gives back the same bytes.
+///
+/// Fails with [`KeyboxError::MalformedKeybox`] if a field or count is too
+/// large for its `u32`, i.e. 4 GiB or more.
+pub fn to_binary(keybox: &Keybox) -> Result<Vec<u8>, KeyboxError> {
+    let mut out = Vec::new();
+    out.extend_from_slice(BINARY_MAGIC);
+    out.push(BINARY_VERSION);
+    push_field(&mut out, keybox.device_id.as_deref().unwrap_or_default().as_bytes(), "device id")?;
+    push_u32(&mut out, keybox.keys.len(), "key count")?;
+    for (i, key) in (1..).zip(&keybox.keys) {
+        out.push(match key.algorithm {
+            KeyAlgorithm::Ecdsa => 0,
+            KeyAlgorithm::Rsa => 1,
+        });
+        push_u32(&mut out, key.certificates.len(), &format!("key {} certificate count", i))?;
+        for (n, cert) in (1..).zip(&key.certificates) {
+            push_field(&mut out, cert, &format!("key {} certificate {}", i, n))?;
+        }
+        push_field(&mut out, key.private_key_bytes().unwrap_or_default(), &format!("key {} private key", i))?;
+    }
+    Ok(out)
+}
+
+fn malformed(reason: &str) -> KeyboxError {
+    KeyboxError::MalformedKeybox(format!("invalid keybox binary: {}", reason))
+}
+
+// Takes the fields of a binary keybox off the front of the bytes in order, naming
+// the field that is cut short in errors
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, field: &str) -> Result<&'a [u8], KeyboxError> {
+        if self.0.len() < len {
+            return Err(malformed(&format!("{} is truncated", field)));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self, field: &str) -> Result<usize, KeyboxError> {
+        let bytes = self.take(4, field)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn field(&mut self, field: &str) -> Result<&'a [u8], KeyboxError> {
+        let len = self.u32(field)?;
+        self.take(len, field)
+    }
+}
+
+/// Reconstructs a [`Keybox`] from bytes produced by [`to_binary`].
+///
+/// Each key's `declared_algorithm` is the canonical `ecdsa` or `rsa`. Fails
+/// with [`KeyboxError::MalformedKeybox`] on a wrong magic or version, an
+/// unknown algorithm, a device id that is not UTF-8, a field cut short or
+/// bytes left after the last key.
+pub fn from_binary(bytes: &[u8]) -> Result<Keybox, KeyboxError> {
+    let mut reader = Reader(bytes);
+    if reader.take(BINARY_MAGIC.len(), "magic")? != BINARY_MAGIC {
+        return Err(malformed("wrong magic"));
+    }
+    let version = reader.take(1, "version")?[0];
+    if version != BINARY_VERSION {
+        return Err(malformed(&format!("unsupported version {}", version)));
+    }
+    let device_id = core::str::from_utf8(reader.field("device id")?).map_err(|_| malformed("device id is not UTF-8"))?;
+    let mut keybox = Keybox {
+        device_id: Some(device_id.to_string()).filter(|device_id| !device_id.is_empty()),
+        ..Keybox::default()
+    };
+
+    for i in 1..=reader.u32("key count")? {
+        let algorithm = match reader.take(1, &format!("key {} algorithm", i))?[0] {
+            0 => KeyAlgorithm::Ecdsa,
+            1 => KeyAlgorithm::Rsa,
+            other => return Err(malformed(&format!("key {} has unknown algorithm {}", i, other))),
+        };
+        let mut key = KeyEntry::new(algorithm);
+        for n in 1..=reader.u32(&format!("key {} certificate count", i))? {
+            key.certificates.push(reader.field(&format!("key {} certificate {}", i, n))?.to_vec());
+        }
+        let private_key = reader.field(&format!("key {} private key", i))?;
+        if !private_key.is_empty() {
+            key.private_key = Some(wrap_private_key(private_key.to_vec()));
+        }
+        keybox.keys.push(key);
+    }
+
+    if !reader.0.is_empty() {
+        return Err(malformed(&format!("{} bytes after the last key", reader.0.len())));
+    }
+    Ok(keybox)
+}
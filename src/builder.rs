@@ -0,0 +1,82 @@
+// src/builder.rs
+use crate::key::detect_format;
+use crate::model::{wrap_private_key, KeyAlgorithm, KeyEntry, Keybox};
+use crate::writer::to_xml;
+
+/// Builds a [`Keybox`] in memory, e.g. for tests or for generating keybox XML
+/// with [`to_xml`](KeyboxBuilder::to_xml).
+///
+/// Each algorithm gets one key, created by the first certificate or private
+/// key added for it. Private key formats are detected from the DER.
+#[derive(Debug, Clone, Default)]
+pub struct KeyboxBuilder {
+    keybox: Keybox,
+}
+
+impl KeyboxBuilder {
+    /// Starts an empty keybox with no device id and no keys.
+    pub fn new() -> Self {
+        KeyboxBuilder::default()
+    }
+
+    /// Sets the `DeviceID` attribute.
+    pub fn device_id(mut self, device_id: impl Into<String>) -> Self {
+        self.keybox.device_id = Some(device_id.into());
+        self
+    }
+
+    /// Appends a DER certificate to the EC chain, which is built leaf first.
+    pub fn add_ec_certificate(self, der: impl Into<Vec<u8>>) -> Self {
+        self.add_certificate(KeyAlgorithm::Ecdsa, der.into())
+    }
+
+    /// Sets the EC private key, in SEC1 or PKCS#8 DER.
+    pub fn ec_private_key(self, der: impl Into<Vec<u8>>) -> Self {
+        self.private_key(KeyAlgorithm::Ecdsa, der.into())
+    }
+
+    /// Appends a DER certificate to the RSA chain, which is built leaf first.
+    pub fn add_rsa_certificate(self, der: impl Into<Vec<u8>>) -> Self {
+        self.add_certificate(KeyAlgorithm::Rsa, der.into())
+    }
+
+    /// Sets the RSA private key, in PKCS#1 or PKCS#8 DER.
+    pub fn rsa_private_key(self, der: impl Into<Vec<u8>>) -> Self {
+        self.private_key(KeyAlgorithm::Rsa, der.into())
+    }
+
+    /// Returns the keybox built so far.
+    pub fn build(self) -> Keybox {
+        self.keybox
+    }
+
+    /// Serializes the keybox built so far as keybox XML; see [`to_xml`].
+    pub fn to_xml(&self) -> String {
+        to_xml(&self.keybox)
+    }
+
+    // The key of `algorithm`, created on first use
+    fn key_mut(&mut self, algorithm: KeyAlgorithm) -> &mut KeyEntry {
+        let keys = &mut self.keybox.keys;
+        let index = match keys.iter().position(|key| key.algorithm == algorithm) {
+            Some(index) => index,
+            None => {
+                keys.push(KeyEntry::new(algorithm));
+                keys.len() - 1
+            }
+        };
+        &mut keys[index]
+    }
+
+    fn add_certificate(mut self, algorithm: KeyAlgorithm, der: Vec<u8>) -> Self {
+        self.key_mut(algorithm).certificates.push(der);
+        self
+    }
+
+    fn private_key(mut self, algorithm: KeyAlgorithm, der: Vec<u8>) -> Self {
+        let key = self.key_mut(algorithm);
+        key.private_key_format = detect_format(&der);
+        key.private_key = Some(wrap_private_key(der));
+        self
+    }
+}
@@ -0,0 +1,515 @@
+// src/certificate.rs
+use crate::error::KeyboxError;
+
+// Tag byte of a constructed DER SEQUENCE, which every X.509 certificate starts with
+const DER_SEQUENCE_TAG: u8 = 0x30;
+
+// Checks that the decoded certificate is a DER X.509 structure. Without the `x509`
+// feature only the outer SEQUENCE tag is checked; with `verify` nothing may follow
+// the certificate.
+pub(crate) fn validate_certificate(der: &[u8], context: &str) -> Result<(), KeyboxError> {
+    let invalid = |reason: String| KeyboxError::InvalidCertificate {
+        context: context.to_string(),
+        reason,
+    };
+
+    if der.first() != Some(&DER_SEQUENCE_TAG) {
+        return Err(invalid("does not start with a DER SEQUENCE".to_string()));
+    }
+
+    #[cfg(feature = "x509")]
+    #[cfg_attr(not(feature = "verify"), allow(unused_variables))]
+    let (rest, _) = x509_parser::parse_x509_certificate(der).map_err(|e| invalid(e.to_string()))?;
+
+    // Bytes past the length the DER declares, e.g. a second certificate pasted into
+    // the same body without its own PEM block
+    #[cfg(feature = "verify")]
+    if !rest.is_empty() {
+        let declared = der.len() - rest.len();
+        return Err(invalid(format!(
+            "DER declares {} bytes but {} were decoded, leaving {} trailing bytes",
+            declared,
+            der.len(),
+            rest.len()
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "x509")]
+fn parse<'a>(der: &'a [u8], context: &str) -> Result<x509_parser::certificate::X509Certificate<'a>, KeyboxError> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).map_err(|e| KeyboxError::InvalidCertificate {
+        context: context.to_string(),
+        reason: e.to_string(),
+    })?;
+    Ok(cert)
+}
+
+/// Returns the `(notBefore, notAfter)` validity period of a DER certificate as
+/// Unix timestamps.
+#[cfg(feature = "x509")]
+pub fn validity(der: &[u8]) -> Result<(i64, i64), KeyboxError> {
+    let cert = parse(der, "certificate")?;
+    let validity = cert.validity();
+    Ok((validity.not_before.timestamp(), validity.not_after.timestamp()))
+}
+
+/// Returns the serial number of a DER certificate as lowercase hex of its
+/// big-endian bytes, since serials may be up to 20 bytes long.
+#[cfg(feature = "x509")]
+pub fn serial_hex(der: &[u8]) -> Result<String, KeyboxError> {
+    let cert = parse(der, "certificate")?;
+    Ok(cert.raw_serial().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Curve name expected for attestation EC keys.
+pub const P256_CURVE: &str = "prime256v1";
+
+/// Returns the named curve of a DER certificate's EC public key, such as
+/// `prime256v1`, or `None` if the key is not an EC key. Curves without a known
+/// name are returned as a dotted OID.
+#[cfg(feature = "x509")]
+pub fn ec_curve(der: &[u8]) -> Result<Option<String>, KeyboxError> {
+    let cert = parse(der, "certificate")?;
+    let algorithm = &cert.public_key().algorithm;
+    if algorithm.algorithm.to_id_string() != "1.2.840.10045.2.1" {
+        return Ok(None);
+    }
+
+    let curve = algorithm.parameters.as_ref().and_then(|params| params.as_oid().ok());
+    Ok(curve.map(|oid| match oid.to_id_string().as_str() {
+        "1.2.840.10045.3.1.7" => P256_CURVE.to_string(),
+        "1.3.132.0.34" => "secp384r1".to_string(),
+        "1.3.132.0.35" => "secp521r1".to_string(),
+        other => other.to_string(),
+    }))
+}
+
+/// Returns the subject distinguished name of a DER certificate, e.g.
+/// `CN=Android Keystore Key, O=Google`.
+#[cfg(feature = "x509")]
+pub fn subject(der: &[u8]) -> Result<String, KeyboxError> {
+    Ok(parse(der, "certificate")?.subject().to_string())
+}
+
+/// Returns the first Common Name of a DER certificate's subject, or `None` if
+/// it has none. Attestation leaves usually carry the device serial here.
+#[cfg(feature = "x509")]
+pub fn subject_common_name(der: &[u8]) -> Result<Option<String>, KeyboxError> {
+    let cert = parse(der, "certificate")?;
+    let cn = cert.subject().iter_common_name().next();
+    Ok(cn.and_then(|cn| cn.as_str().ok()).map(str::to_string))
+}
+
+/// OID of the Android key attestation extension.
+pub const ATTESTATION_EXTENSION_OID: &str = "1.3.6.1.4.1.11129.2.1.17";
+
+/// Returns the value of a DER certificate's key attestation extension, the
+/// DER `KeyDescription`, or `None` if the certificate has none, as for
+/// intermediates and leaves that are not attestation certificates.
+#[cfg(feature = "x509")]
+pub fn attestation_extension(der: &[u8]) -> Result<Option<Vec<u8>>, KeyboxError> {
+    let cert = parse(der, "certificate")?;
+    let extension = cert
+        .extensions()
+        .iter()
+        .find(|extension| extension.oid.to_id_string() == ATTESTATION_EXTENSION_OID);
+    Ok(extension.map(|extension| extension.value.to_vec()))
+}
+
+/// Returns true if a DER certificate's issuer is its own subject, as for the
+/// root at the end of a full chain.
+#[cfg(feature = "x509")]
+pub fn is_self_issued(der: &[u8]) -> Result<bool, KeyboxError> {
+    let cert = parse(der, "certificate")?;
+    Ok(cert.issuer().as_raw() == cert.subject().as_raw())
+}
+
+// Checks that each certificate in a leaf-first chain was issued by the next one, by
+// comparing its issuer with the next certificate's subject. The error names the
+// first certificate whose link is broken.
+#[cfg(feature = "verify")]
+pub(crate) fn verify_chain_order(chain: &[Vec<u8>], context: &str) -> Result<(), KeyboxError> {
+    for (i, pair) in chain.windows(2).enumerate() {
+        let cert_context = format!("{} certificate {}", context, i + 1);
+        let cert = parse(&pair[0], &cert_context)?;
+        let issuer = parse(&pair[1], &format!("{} certificate {}", context, i + 2))?;
+        if cert.issuer().as_raw() != issuer.subject().as_raw() {
+            return Err(KeyboxError::InvalidCertificate {
+                context: cert_context,
+                reason: format!(
+                    "issuer {} does not match the subject {} of certificate {}; the chain must be ordered leaf first",
+                    cert.issuer(),
+                    issuer.subject(),
+                    i + 2
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Rejects EC leaf certificates whose key is not on P-256, which attestation requires
+#[cfg(feature = "verify")]
+pub(crate) fn verify_p256(leaf: &[u8], context: &str) -> Result<(), KeyboxError> {
+    match ec_curve(leaf)? {
+        Some(curve) if curve == P256_CURVE => Ok(()),
+        curve => Err(KeyboxError::InvalidCertificate {
+            context: context.to_string(),
+            reason: format!(
+                "key uses curve {}, expected {}",
+                curve.as_deref().unwrap_or("none"),
+                P256_CURVE
+            ),
+        }),
+    }
+}
+
+// Checks that the EC private key (SEC1 or PKCS#8 DER) belongs to the public key in
+// the leaf certificate, catching keys swapped in from a different keybox.
+#[cfg(feature = "verify")]
+pub(crate) fn verify_ec_key_matches(private_key: &[u8], leaf: &[u8], context: &str) -> Result<(), KeyboxError> {
+    use p256::pkcs8::DecodePrivateKey;
+    use p256::{PublicKey, SecretKey};
+
+    let secret = SecretKey::from_sec1_der(private_key)
+        .or_else(|_| SecretKey::from_pkcs8_der(private_key))
+        .map_err(|e| KeyboxError::InvalidPrivateKey {
+            context: context.to_string(),
+            reason: e.to_string(),
+        })?;
+
+    let cert = parse(leaf, &format!("{} leaf certificate", context))?;
+    let cert_key = PublicKey::from_sec1_bytes(&cert.public_key().subject_public_key.data);
+
+    if cert_key.ok() != Some(secret.public_key()) {
+        return Err(KeyboxError::KeyMismatch {
+            context: context.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the uncompressed SEC1 point (`04 || X || Y`, 65 bytes) of an EC
+/// key's P-256 public key, taken from its leaf certificate or, without one,
+/// derived from its private key. Returns `None` if the key has neither.
+#[cfg(feature = "verify")]
+pub fn ec_public_key(key: &crate::model::KeyEntry) -> Result<Option<Vec<u8>>, KeyboxError> {
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+    use p256::pkcs8::DecodePrivateKey;
+    use p256::{PublicKey, SecretKey};
+
+    let public_key = match (key.leaf(), &key.private_key) {
+        (Some(leaf), _) => {
+            let cert = parse(leaf, "leaf certificate")?;
+            PublicKey::from_sec1_bytes(&cert.public_key().subject_public_key.data).map_err(|e| {
+                KeyboxError::InvalidCertificate {
+                    context: "leaf certificate".to_string(),
+                    reason: format!("public key is not a P-256 point: {}", e),
+                }
+            })?
+        }
+        (None, Some(private_key)) => SecretKey::from_sec1_der(private_key)
+            .or_else(|_| SecretKey::from_pkcs8_der(private_key))
+            .map_err(|e| KeyboxError::InvalidPrivateKey {
+                context: "private key".to_string(),
+                reason: e.to_string(),
+            })?
+            .public_key(),
+        (None, None) => return Ok(None),
+    };
+
+    let point = public_key.to_encoded_point(false);
+    match (point.x(), point.y()) {
+        (Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => Ok(Some(point.as_bytes().to_vec())),
+        _ => Err(KeyboxError::InvalidCertificate {
+            context: "leaf certificate".to_string(),
+            reason: "public key coordinates are not 32 bytes each".to_string(),
+        }),
+    }
+}
+
+// Checks that `cert` is within its validity period and, if `issuer` is given, that its
+// signature verifies against the issuer's public key
+#[cfg(feature = "verify")]
+fn verify_link(
+    cert: &x509_parser::certificate::X509Certificate,
+    issuer: Option<&x509_parser::certificate::X509Certificate>,
+    context: &str,
+    issuer_name: &str,
+) -> Result<(), KeyboxError> {
+    let invalid = |reason: String| KeyboxError::InvalidCertificate {
+        context: context.to_string(),
+        reason,
+    };
+
+    if !cert.validity().is_valid() {
+        return Err(invalid(format!(
+            "not valid now (valid from {} to {})",
+            cert.validity().not_before,
+            cert.validity().not_after
+        )));
+    }
+    if let Some(issuer) = issuer {
+        cert.verify_signature(Some(issuer.public_key()))
+            .map_err(|e| invalid(format!("signature does not verify against {}: {}", issuer_name, e)))?;
+    }
+    Ok(())
+}
+
+/// Verifies that every key's certificate chain in `keybox` terminates at one of
+/// the DER certificates in `roots`.
+///
+/// Each certificate must be currently valid and signed by the next one in its
+/// chain. The last certificate must either be one of `roots` or be signed by
+/// one of them. The error names the certificate whose link failed.
+#[cfg(feature = "verify")]
+pub fn verify_against_roots(keybox: &crate::model::Keybox, roots: &[&[u8]]) -> Result<(), KeyboxError> {
+    if roots.is_empty() {
+        return Err(KeyboxError::Config("no trusted roots given".to_string()));
+    }
+    let parsed_roots = roots
+        .iter()
+        .enumerate()
+        .map(|(i, root)| parse(root, &format!("root {}", i + 1)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if keybox.keys.is_empty() {
+        return Err(KeyboxError::MalformedKeybox("keybox has no keys to verify".to_string()));
+    }
+
+    for (n, key) in keybox.keys.iter().enumerate() {
+        let prefix = format!("key {} ({})", n + 1, key.algorithm.prefix());
+        let chain = key
+            .certificates
+            .iter()
+            .enumerate()
+            .map(|(i, der)| parse(der, &format!("{} certificate {}", prefix, i + 1)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(last) = chain.last() else {
+            return Err(KeyboxError::InvalidCertificate {
+                context: prefix,
+                reason: "has no certificate chain".to_string(),
+            });
+        };
+
+        for (i, cert) in chain.iter().enumerate() {
+            let context = format!("{} certificate {}", prefix, i + 1);
+            let issuer_name = format!("certificate {}", i + 2);
+            verify_link(cert, chain.get(i + 1), &context, &issuer_name)?;
+        }
+
+        // The chain may end with the root itself or with a certificate the root issued
+        let context = format!("{} certificate {}", prefix, chain.len());
+        let last_der = key.certificates.last().map(Vec::as_slice);
+        let anchored = roots.iter().zip(&parsed_roots).any(|(der, root)| {
+            Some(*der) == last_der || last.verify_signature(Some(root.public_key())).is_ok()
+        });
+        if !anchored {
+            return Err(KeyboxError::InvalidCertificate {
+                context,
+                reason: format!("is not issued by any trusted root (issuer {})", last.issuer()),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// The key chosen for one algorithm by [`choose_keys_by`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChoice {
+    pub algorithm: crate::model::KeyAlgorithm,
+    /// Position of the chosen key among the keys of its algorithm, counting
+    /// from 1, or `None` if no key was usable and the order was kept.
+    pub chosen: Option<usize>,
+    /// Score of the chosen key, e.g. its leaf's `notAfter`.
+    pub score: Option<i64>,
+    /// Number of keys of the algorithm.
+    pub keys: usize,
+    /// Number of those keys that were not usable.
+    pub unusable: usize,
+}
+
+/// Moves the chosen key of each algorithm in `keybox` to the front of the keys
+/// of that algorithm, so that it is the one generated as `ec` or `rsa`.
+///
+/// `score` rates each key, with `None` marking a key that must not be used.
+/// The highest score wins and ties go to the earlier key. If no key of an
+/// algorithm is usable, its keys keep their order. Keys of other algorithms
+/// stay where they are.
+pub fn choose_keys_by<F>(keybox: &mut crate::model::Keybox, mut score: F) -> Result<Vec<KeyChoice>, KeyboxError>
+where
+    F: FnMut(&crate::model::KeyEntry) -> Result<Option<i64>, KeyboxError>,
+{
+    use crate::model::KeyAlgorithm;
+
+    let mut choices = Vec::new();
+    for algorithm in [KeyAlgorithm::Ecdsa, KeyAlgorithm::Rsa] {
+        let positions: Vec<usize> = (0..keybox.keys.len()).filter(|&i| keybox.keys[i].algorithm == algorithm).collect();
+        if positions.is_empty() {
+            continue;
+        }
+        let mut best: Option<(usize, i64)> = None;
+        let mut unusable = 0;
+        for (nth, &i) in positions.iter().enumerate() {
+            match score(&keybox.keys[i])? {
+                Some(value) if best.is_none_or(|(_, best)| value > best) => best = Some((nth, value)),
+                Some(_) => {}
+                None => unusable += 1,
+            }
+        }
+        // Rotating keeps every other key, of either algorithm, in document order
+        if let Some((nth, _)) = best {
+            keybox.keys[positions[0]..=positions[nth]].rotate_right(1);
+        }
+        choices.push(KeyChoice {
+            algorithm,
+            chosen: best.map(|(nth, _)| nth + 1),
+            score: best.map(|(_, score)| score),
+            keys: positions.len(),
+            unusable,
+        });
+    }
+    Ok(choices)
+}
+
+/// Chooses, for each algorithm, the key whose leaf certificate is valid at
+/// `now` and expires last; see [`choose_keys_by`], with the leaf's `notAfter`
+/// as the score. Keys without a leaf, or whose leaf is expired or not yet valid
+/// at `now`, are unusable.
+#[cfg(feature = "x509")]
+pub fn choose_freshest_keys(keybox: &mut crate::model::Keybox, now: i64) -> Result<Vec<KeyChoice>, KeyboxError> {
+    choose_keys_by(keybox, |key| {
+        let Some(leaf) = key.leaf() else {
+            return Ok(None);
+        };
+        let (not_before, not_after) = validity(leaf)?;
+        Ok((not_before <= now && now <= not_after).then_some(not_after))
+    })
+}
+
+/// What could be read of one certificate for a [`ChainReport`].
+#[cfg(feature = "x509")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateSummary {
+    /// Subject distinguished name, e.g. `CN=Android Keystore Key, O=Google`.
+    pub subject: String,
+    pub issuer: String,
+    /// `(notBefore, notAfter)` as Unix timestamps.
+    pub validity: (i64, i64),
+    /// True if the issuer is the certificate's own subject, as for a root.
+    pub self_issued: bool,
+}
+
+/// One certificate of a [`ChainReport`] and its link to the next one.
+#[cfg(feature = "x509")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainLink {
+    /// The certificate's summary, or why it could not be parsed.
+    pub certificate: Result<CertificateSummary, String>,
+    /// Whether the issuer equals the next certificate's subject, or `None` for
+    /// the last certificate or if either of the two could not be parsed.
+    pub issued_by_next: Option<bool>,
+}
+
+/// The structure of a key's certificate chain, leaf first, for review.
+///
+/// Unlike [`verify_against_roots`] nothing is verified or required: each
+/// certificate's subject, issuer and validity are listed with whether it
+/// names the next certificate as its issuer, and a certificate that cannot be
+/// parsed is listed with the reason instead. Its `Display` gives one line per
+/// certificate.
+#[cfg(feature = "x509")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainReport {
+    pub links: Vec<ChainLink>,
+}
+
+#[cfg(feature = "x509")]
+impl ChainReport {
+    /// Returns true if the chain has at least one certificate and every
+    /// certificate was parsed and names the next one as its issuer.
+    pub fn is_linked(&self) -> bool {
+        let last = self.links.len().saturating_sub(1);
+        !self.links.is_empty()
+            && self.links[..last].iter().all(|link| link.issued_by_next == Some(true))
+            && self.links.iter().all(|link| link.certificate.is_ok())
+    }
+}
+
+/// Describes the chain of `key`; see [`ChainReport`].
+#[cfg(feature = "x509")]
+pub fn chain_report(key: &crate::model::KeyEntry) -> ChainReport {
+    let parsed: Vec<_> = key
+        .certificates
+        .iter()
+        .map(|der| x509_parser::parse_x509_certificate(der).map(|(_, cert)| cert).map_err(|e| e.to_string()))
+        .collect();
+    let links = parsed
+        .iter()
+        .enumerate()
+        .map(|(i, cert)| ChainLink {
+            certificate: cert.as_ref().map_err(Clone::clone).map(|cert| CertificateSummary {
+                subject: cert.subject().to_string(),
+                issuer: cert.issuer().to_string(),
+                validity: (cert.validity().not_before.timestamp(), cert.validity().not_after.timestamp()),
+                self_issued: cert.issuer().as_raw() == cert.subject().as_raw(),
+            }),
+            issued_by_next: match (cert, parsed.get(i + 1)) {
+                (Ok(cert), Some(Ok(next))) => Some(cert.issuer().as_raw() == next.subject().as_raw()),
+                _ => None,
+            },
+        })
+        .collect();
+    ChainReport { links }
+}
+
+// Formats a Unix timestamp like the certificate tools do, e.g. `Oct 14 10:22:40 2026
+// +00:00`, or as the number if it is out of range
+#[cfg(feature = "x509")]
+pub(crate) fn format_time(timestamp: i64) -> String {
+    use x509_parser::time::ASN1Time;
+
+    ASN1Time::from_timestamp(timestamp).map_or(timestamp.to_string(), |time| time.to_string())
+}
+
+#[cfg(feature = "x509")]
+impl core::fmt::Display for ChainReport {
+    /// Formats each certificate as one line, e.g. `certificate 1: subject CN=Leaf;
+    /// issuer CN=Root; valid Oct 14 10:22:40 2026 +00:00 to ...; issued by
+    /// certificate 2`.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.links.is_empty() {
+            return writeln!(f, "no certificates");
+        }
+        for (i, link) in self.links.iter().enumerate() {
+            let summary = match &link.certificate {
+                Ok(summary) => summary,
+                Err(reason) => {
+                    writeln!(f, "certificate {}: unreadable: {}", i + 1, reason)?;
+                    continue;
+                }
+            };
+            let (not_before, not_after) = summary.validity;
+            write!(
+                f,
+                "certificate {}: subject {}; issuer {}; valid {} to {}",
+                i + 1,
+                summary.subject,
+                summary.issuer,
+                format_time(not_before),
+                format_time(not_after)
+            )?;
+            match link.issued_by_next {
+                Some(true) => writeln!(f, "; issued by certificate {}", i + 2)?,
+                Some(false) => writeln!(f, "; NOT issued by certificate {}", i + 2)?,
+                None if summary.self_issued => writeln!(f, "; self-issued")?,
+                None if i + 1 == self.links.len() => writeln!(f, "; last in chain, not self-issued")?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
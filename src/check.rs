@@ -0,0 +1,184 @@
+// src/check.rs
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::error::KeyboxError;
+use crate::parser::{decode_certificate, read_keyboxes, repeated_device_ids};
+use crate::pem::decode_base64;
+
+/// Outcome of checking one item of a keybox, such as `keybox 1 EC certificate 2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckStatus {
+    pub item: String,
+    /// What is wrong with the item, or `None` if it is fine.
+    pub problem: Option<String>,
+}
+
+impl CheckStatus {
+    fn new(item: impl Into<String>, result: Result<(), KeyboxError>) -> Self {
+        CheckStatus {
+            item: item.into(),
+            problem: result.err().map(|e| reason(&e)),
+        }
+    }
+
+    fn problem(item: impl Into<String>, problem: impl Into<String>) -> Self {
+        CheckStatus {
+            item: item.into(),
+            problem: Some(problem.into()),
+        }
+    }
+
+    /// Returns true if the item has no problem.
+    pub fn is_ok(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+impl fmt::Display for CheckStatus {
+    /// Formats the status as one line, e.g. `FAIL keybox 1 EC private key: missing`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.problem {
+            None => write!(f, "ok   {}", self.item),
+            Some(problem) => write!(f, "FAIL {}: {}", self.item, problem),
+        }
+    }
+}
+
+// Describes an error without repeating the item it belongs to
+fn reason(e: &KeyboxError) -> String {
+    match e {
+        KeyboxError::Base64Decode { length, source, .. } => {
+            format!("invalid base64 ({} characters): {}", length, source)
+        }
+        KeyboxError::InvalidCertificate { reason, .. } | KeyboxError::InvalidPrivateKey { reason, .. } => {
+            reason.clone()
+        }
+        KeyboxError::KeyMismatch { .. } => "does not match its leaf certificate".to_string(),
+        KeyboxError::MalformedKeybox(reason) => reason.clone(),
+        e => e.to_string(),
+    }
+}
+
+// Reports certificates that have already expired
+#[cfg(feature = "x509")]
+fn check_expiry(der: &[u8]) -> Result<(), KeyboxError> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let (_, not_after) = crate::certificate::validity(der)?;
+    if not_after < now {
+        return Err(KeyboxError::MalformedKeybox(format!(
+            "expired {} days ago",
+            (now - not_after) / 86400
+        )));
+    }
+    Ok(())
+}
+
+/// Checks the keybox XML file at `path`; see [`check_keybox_reader`].
+pub fn check_keybox(path: &Path) -> Result<Vec<CheckStatus>, KeyboxError> {
+    check_keybox_reader(BufReader::new(File::open(path)?))
+}
+
+/// Checks every part of the keybox XML from `reader` without stopping at the
+/// first problem, returning one status per problem with the format version or
+/// declared counts, key skipped for an unrecognized algorithm, certificate and
+/// private key.
+///
+/// Certificates and keys are decoded as by
+/// [`parse_keybox_reader`](crate::parse_keybox_reader). With the `x509`
+/// feature expired certificates are reported, and with `verify` so are broken
+/// chains and keys that do not match their leaf. Only unreadable input or
+/// malformed XML is returned as an error.
+pub fn check_keybox_reader<R: Read>(reader: R) -> Result<Vec<CheckStatus>, KeyboxError> {
+    let mut statuses = Vec::new();
+    let keyboxes = read_keyboxes(reader, &mut |item, e| {
+        statuses.push(CheckStatus::new(item, Err(e)));
+        Ok(())
+    })?;
+    if keyboxes.is_empty() {
+        statuses.push(CheckStatus::problem("keybox", "no keyboxes found"));
+    }
+    for (first, repeat) in repeated_device_ids(keyboxes.iter().map(|keybox| keybox.device_id.as_deref())) {
+        let problem = format!("same DeviceID as keybox {}", first);
+        statuses.push(CheckStatus::problem(format!("keybox {} DeviceID", repeat), problem));
+    }
+
+    for (i, keybox) in keyboxes.iter().enumerate() {
+        for algorithm in &keybox.skipped_algorithms {
+            let problem = format!("skipped key with unrecognized algorithm {:?}", algorithm);
+            statuses.push(CheckStatus::problem(format!("keybox {}", i + 1), problem));
+        }
+        if keybox.keys.is_empty() {
+            statuses.push(CheckStatus::problem(format!("keybox {}", i + 1), "no keys found"));
+        }
+
+        for (key, context) in keybox.keys.iter().zip(keybox.key_contexts(i + 1)) {
+            if key.certs.is_empty() {
+                statuses.push(CheckStatus::problem(format!("{} certificate chain", context), "missing"));
+            }
+            let mut chain = Vec::new();
+            for (n, cert) in key.certs.iter().enumerate() {
+                let item = format!("{} certificate {}", context, n + 1);
+                let result = decode_certificate(cert, &item).map(|(der, _)| der);
+                #[cfg(feature = "x509")]
+                let result = result.and_then(|der| check_expiry(&der).map(|_| der));
+                match result {
+                    Ok(der) => {
+                        chain.push(der);
+                        statuses.push(CheckStatus::new(item, Ok(())));
+                    }
+                    Err(e) => statuses.push(CheckStatus::new(item, Err(e))),
+                }
+            }
+
+            // The chain is only checked once every certificate in it decoded
+            #[cfg(feature = "verify")]
+            let complete = !chain.is_empty() && chain.len() == key.certs.len();
+            #[cfg(feature = "verify")]
+            if complete {
+                let result = crate::certificate::verify_chain_order(&chain, &context);
+                statuses.push(CheckStatus::new(format!("{} chain order", context), result));
+            }
+
+            'private_key: {
+                let item = format!("{} private key", context);
+                let Some(private_key) = &key.private_key else {
+                    statuses.push(CheckStatus::problem(item, "missing"));
+                    break 'private_key;
+                };
+                let decoded = decode_base64(private_key, item.clone());
+                #[cfg(feature = "verify")]
+                let decoded = decoded.and_then(|der| {
+                    crate::parser::check_private_key_label(key.algorithm, key.private_key_format.as_ref(), &item)?;
+                    Ok(der)
+                });
+
+                #[cfg(feature = "verify")]
+                if let (crate::model::KeyAlgorithm::Ecdsa, true, Ok(private_key)) =
+                    (key.algorithm, complete, &decoded)
+                {
+                    let leaf = &chain[0];
+                    let result = crate::certificate::verify_p256(leaf, &context)
+                        .and_then(|_| crate::certificate::verify_ec_key_matches(private_key, leaf, &item));
+                    statuses.push(CheckStatus::new(item, result));
+                    break 'private_key;
+                }
+
+                statuses.push(CheckStatus::new(item, decoded.map(|_| ())));
+            }
+
+            // Further private keys are only decoded; the first is the one in use
+            for (n, private_key) in key.extra_private_keys.iter().enumerate() {
+                let item = format!("{} private key {}", context, n + 2);
+                let result = decode_base64(private_key, item.clone()).map(|_| ());
+                statuses.push(CheckStatus::new(item, result));
+            }
+        }
+    }
+
+    Ok(statuses)
+}
@@ -0,0 +1,872 @@
+// src/codegen.rs
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use sha2::{Digest, Sha256};
+
+use crate::error::KeyboxError;
+use crate::model::{KeyAlgorithm, KeyEntry, Keybox};
+use crate::pem::der_to_pem;
+
+// Version of this crate, recorded as the generator of each file
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Column limit of rustfmt's default configuration, which the generated lines follow so
+// that running rustfmt over the generated file changes nothing
+const MAX_WIDTH: usize = 100;
+
+// Function to write bytes in groups of `per_line` per line, each line prefixed with `indent`.
+// Without `per_line`, each line holds as many bytes as fit in MAX_WIDTH, as rustfmt lays
+// them out.
+fn write_bytes<W: Write>(output_file: &mut W, bytes: &[u8], indent: &str, per_line: Option<usize>) -> io::Result<()> {
+    // Each byte takes `0x00,` plus a separating space
+    let per_line = per_line.unwrap_or((MAX_WIDTH + 1 - indent.len()) / 6).max(1);
+    for (i, byte) in bytes.iter().enumerate() {
+        if i % per_line == 0 {
+            if i != 0 {
+                writeln!(output_file)?;
+            }
+            write!(output_file, "{}", indent)?;
+        } else {
+            write!(output_file, " ")?;
+        }
+        write!(output_file, "0x{:02x},", byte)?; // Write each byte
+    }
+    writeln!(output_file)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Strict and reserved keywords of the 2015 to 2024 editions, none of which can name a
+// module, not even as a raw identifier for `self`, `super`, `crate` and `Self`
+const KEYWORDS: &[&str] = &[
+    "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro",
+    "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "self", "static", "struct", "super",
+    "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Whether `name` can name a module in generated code, e.g. in a `pub mod`
+/// line of an index of generated files: an ASCII identifier other than `_`
+/// that is not a Rust keyword.
+pub fn is_module_name(name: &str) -> bool {
+    is_identifier(name) && name != "_" && !KEYWORDS.contains(&name)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Value of a generated constant, kept structured until it is written so it can be laid
+// out to fit its line
+enum Value {
+    // Source written as is, e.g. `"test-device"` or `include_bytes!("ec_private_key.der")`
+    Code(String),
+    // A byte array, as `&[ ... ]` when `reference` is set or `[ ... ]` otherwise
+    Bytes { bytes: Vec<u8>, reference: bool },
+    // A slice of other constants, e.g. `&[CERTIFICATE_1, CERTIFICATE_2]`
+    List(Vec<String>),
+}
+
+// A constant generated inside an algorithm module, e.g. `CERTIFICATE_1` in `ec`, with
+// the lines of its doc comment
+struct Constant {
+    name: String,
+    ty: String,
+    value: Value,
+    doc: Vec<String>,
+}
+
+impl Constant {
+    fn new(name: impl Into<String>, ty: impl Into<String>, value: impl Into<String>) -> Self {
+        Constant::with_value(name, ty, Value::Code(value.into()))
+    }
+
+    fn bytes(name: impl Into<String>, ty: impl Into<String>, bytes: &[u8], reference: bool) -> Self {
+        let bytes = bytes.to_vec();
+        Constant::with_value(name, ty, Value::Bytes { bytes, reference })
+    }
+
+    fn with_value(name: impl Into<String>, ty: impl Into<String>, value: Value) -> Self {
+        Constant {
+            name: name.into(),
+            ty: ty.into(),
+            value,
+            doc: Vec::new(),
+        }
+    }
+
+    // Adds a line to the doc comment. Control characters are escaped, as a line break
+    // from a keybox, e.g. in a certificate's CN, would end the comment and let the
+    // rest of the line be compiled as code.
+    fn doc(mut self, line: impl Into<String>) -> Self {
+        let line: String = line.into();
+        let escape = |c: char| if c.is_control() { c.escape_debug().to_string() } else { c.to_string() };
+        self.doc.push(line.chars().map(escape).collect());
+        self
+    }
+
+    // Formats the constant as rustfmt would at `indent`: on one line if it fits, else with
+    // the value on the next line or, for arrays, one line per group of bytes or per item
+    fn to_source(&self, indent: &str, bytes_per_line: Option<usize>) -> String {
+        let doc: String = self
+            .doc
+            .iter()
+            .map(|line| if line.is_empty() { format!("{}///\n", indent) } else { format!("{}/// {}\n", indent, line) })
+            .collect();
+        format!("{}{}", doc, self.to_code(indent, bytes_per_line))
+    }
+
+    // The constant itself, without its doc comment
+    fn to_code(&self, indent: &str, bytes_per_line: Option<usize>) -> String {
+        let head = format!("{}pub const {}: {} = ", indent, self.name, self.ty);
+        let inner = format!("{}    ", indent);
+        let (prefix, items) = match &self.value {
+            Value::Code(value) => {
+                // rustfmt leaves lines alone that are too long even with the value moved
+                let fits = head.len() + value.len() < MAX_WIDTH;
+                let fits_wrapped = inner.len() + value.len() < MAX_WIDTH;
+                return if fits || !fits_wrapped {
+                    format!("{}{};", head, value)
+                } else {
+                    format!("{}\n{}{};", head.trim_end(), inner, value)
+                };
+            }
+            Value::Bytes { bytes, reference } => {
+                let items: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+                (if *reference { "&" } else { "" }, items)
+            }
+            Value::List(items) => ("&", items.clone()),
+        };
+
+        let single = format!("{}{}[{}];", head, prefix, items.join(", "));
+        if items.is_empty() || single.len() <= MAX_WIDTH {
+            return single;
+        }
+        let mut body = Vec::new();
+        match &self.value {
+            // Writing to a Vec cannot fail
+            Value::Bytes { bytes, .. } => write_bytes(&mut body, bytes, &inner, bytes_per_line).unwrap(),
+            _ => body.extend(items.iter().flat_map(|item| format!("{}{},\n", inner, item).into_bytes())),
+        }
+        format!("{}{}[\n{}{}];", head, prefix, String::from_utf8(body).unwrap(), indent)
+    }
+}
+
+// Directory that certificates and private keys are written to as `.der` files, and
+// the module whose constants include them
+struct DerFiles<'a> {
+    dir: &'a Path,
+    module: &'a str,
+}
+
+// Value of the byte constant `name`: an inline array, or with `der_files` an
+// `include_bytes!` of a `.der` file written next to the generated file. The file
+// name is relative, so the generated file can be copied along with its `.der` files.
+// Without `reference` the value is the array itself, e.g. for `[u8; N]` constants.
+fn bytes_value(name: &str, bytes: &[u8], der_files: Option<&DerFiles>, reference: bool) -> io::Result<Value> {
+    let Some(der_files) = der_files else {
+        let bytes = bytes.to_vec();
+        return Ok(Value::Bytes { bytes, reference });
+    };
+    let file_name = format!("{}_{}.der", der_files.module, name.to_lowercase());
+    std::fs::write(der_files.dir.join(&file_name), bytes)?;
+    let deref = if reference { "" } else { "*" };
+    Ok(Value::Code(format!("{}include_bytes!({:?})", deref, file_name)))
+}
+
+// Pushes the byte constant `name` as `&[u8]`, documented with `doc`. With the
+// `arrays` feature a `<name>_ARRAY: [u8; N]` constant holds the bytes and `name`
+// refers to it, so the bytes can also be embedded by value in other constants.
+fn push_bytes(
+    constants: &mut Vec<Constant>,
+    name: &str,
+    bytes: &[u8],
+    der_files: Option<&DerFiles>,
+    doc: &[String],
+) -> io::Result<()> {
+    let documented = |constant: Constant| doc.iter().fold(constant, |constant, line| constant.doc(line.as_str()));
+    if !cfg!(feature = "arrays") {
+        let value = bytes_value(name, bytes, der_files, true)?;
+        constants.push(documented(Constant::with_value(name, "&[u8]", value)));
+        return Ok(());
+    }
+    let array = format!("{}_ARRAY", name);
+    constants.push(documented(Constant::new(name, "&[u8]", format!("&{}", array))));
+    let value = bytes_value(name, bytes, der_files, false)?;
+    let array = Constant::with_value(array, format!("[u8; {}]", bytes.len()), value);
+    constants.push(array.doc(format!("[`{}`] as a fixed-size array.", name)));
+    Ok(())
+}
+
+// Pushes the constants of a missing certificate or private key `name`, empty
+fn push_empty_bytes(constants: &mut Vec<Constant>, name: &str, doc: &str) {
+    constants.push(Constant::new(name, "&[u8]", "&[]").doc(doc));
+    if cfg!(feature = "arrays") {
+        let array = Constant::new(format!("{}_ARRAY", name), "[u8; 0]", "[]");
+        constants.push(array.doc(format!("[`{}`] as a fixed-size array.", name)));
+    }
+}
+
+// Builds the certificate and private key constants for one key, documented as
+// belonging to `owner`, e.g. `ECDSA key 1 of keybox "test-device"`. A missing key
+// gets the same constants, empty.
+fn key_constants(
+    algorithm: KeyAlgorithm,
+    key: Option<&KeyEntry>,
+    owner: &str,
+    der_files: Option<&DerFiles>,
+) -> io::Result<Vec<Constant>> {
+    let certs = key.map_or(&[][..], |key| &key.certificates);
+    let private_key = key.and_then(|key| key.private_key.as_ref());
+    let mut constants = Vec::new();
+
+    // The algorithm as the library's enum, for matching on instead of module names.
+    // It is named through the crate, which the library itself also declares, so the
+    // generated file compiles both there and in consumers.
+    let ty = "keybox_parser::KeyAlgorithm";
+    let key_algorithm = Constant::new("KEY_ALGORITHM", ty, format!("{}::{:?}", ty, algorithm));
+    constants.push(key_algorithm.doc(format!("Algorithm of {}.", owner)));
+
+    // Write one constant per certificate. The first three are always written, defaulting
+    // to empty arrays, so code relying on the historical three slots keeps compiling.
+    for i in 1..=certs.len().max(3) {
+        let name = format!("CERTIFICATE_{}", i);
+        let what = format!("certificate {} of the chain of {}", i, owner);
+        if let Some(cert) = certs.get(i - 1) {
+            #[cfg_attr(not(feature = "x509"), allow(unused_mut))]
+            let mut doc = vec![format!("DER of {}.", what)];
+            // Who the certificate is for and when it is valid, as far as it can be read
+            #[cfg(feature = "x509")]
+            {
+                use crate::certificate::{format_time, subject_common_name, validity};
+
+                let cn = subject_common_name(cert).ok().flatten().filter(|cn| !cn.is_empty());
+                let details: Vec<String> = cn
+                    .map(|cn| format!("Subject CN: `{}`.", cn.replace('`', "'")))
+                    .into_iter()
+                    .chain(validity(cert).ok().map(|(from, to)| {
+                        format!("Valid from {} to {}.", format_time(from), format_time(to))
+                    }))
+                    .collect();
+                for line in details {
+                    doc.extend([String::new(), line]);
+                }
+            }
+            push_bytes(&mut constants, &name, cert, der_files, &doc)?;
+            if cfg!(feature = "pem") {
+                let pem = format!("{:?}", der_to_pem(cert, "CERTIFICATE"));
+                let doc = format!("[`{}`] as PEM.", name);
+                constants.push(Constant::new(format!("{}_PEM", name), "&str", pem).doc(doc));
+            }
+            if cfg!(feature = "hex") {
+                let hex = Constant::new(format!("{}_HEX", name), "&str", format!("{:?}", hex(cert)));
+                constants.push(hex.doc(format!("[`{}`] as lowercase hex.", name)));
+            }
+
+            // Fingerprint over the DER bytes, as raw bytes and lowercase hex
+            let fingerprint = Sha256::digest(cert);
+            let sha256 = Constant::bytes(format!("{}_SHA256", name), "[u8; 32]", &fingerprint, false);
+            constants.push(sha256.doc(format!("SHA-256 fingerprint of [`{}`].", name)));
+            let fingerprint_hex = format!("{:?}", hex(&fingerprint));
+            let sha256_hex = Constant::new(format!("{}_SHA256_HEX", name), "&str", fingerprint_hex);
+            constants.push(sha256_hex.doc(format!("SHA-256 fingerprint of [`{}`] as lowercase hex.", name)));
+
+            // Validity period as Unix timestamps. Certificates were already validated while parsing.
+            #[cfg(feature = "x509")]
+            if let Ok((not_before, not_after)) = crate::certificate::validity(cert) {
+                let not_before = Constant::new(format!("{}_NOT_BEFORE", name), "i64", not_before.to_string());
+                constants.push(not_before.doc(format!("Start of the validity of [`{}`], as a Unix timestamp.", name)));
+                let not_after = Constant::new(format!("{}_NOT_AFTER", name), "i64", not_after.to_string());
+                constants.push(not_after.doc(format!("End of the validity of [`{}`], as a Unix timestamp.", name)));
+            }
+
+            // Serial number as hex, for checking against revocation lists. Empty if it
+            // cannot be read; the build script warns about that.
+            #[cfg(feature = "x509")]
+            {
+                let serial = crate::certificate::serial_hex(cert).unwrap_or_default();
+                let serial = Constant::new(format!("{}_SERIAL", name), "&str", format!("{:?}", serial));
+                constants.push(serial.doc(format!("Serial number of [`{}`] as hex, empty if unreadable.", name)));
+            }
+        } else {
+            push_empty_bytes(&mut constants, &name, &format!("DER of {}; empty, as the chain is shorter.", what));
+            if cfg!(feature = "pem") {
+                let pem = Constant::new(format!("{}_PEM", name), "&str", "\"\"");
+                constants.push(pem.doc(format!("[`{}`] as PEM; empty.", name)));
+            }
+            if cfg!(feature = "hex") {
+                let hex = Constant::new(format!("{}_HEX", name), "&str", "\"\"");
+                constants.push(hex.doc(format!("[`{}`] as lowercase hex; empty.", name)));
+            }
+
+            // Missing certificates get an all-zero fingerprint and an empty hex string
+            let sha256 = Constant::new(format!("{}_SHA256", name), "[u8; 32]", "[0; 32]");
+            constants.push(sha256.doc(format!("SHA-256 fingerprint of [`{}`]; all zero, as it is empty.", name)));
+            let sha256_hex = Constant::new(format!("{}_SHA256_HEX", name), "&str", "\"\"");
+            constants.push(sha256_hex.doc(format!("SHA-256 fingerprint of [`{}`] as lowercase hex; empty.", name)));
+
+            #[cfg(feature = "x509")]
+            {
+                let not_before = Constant::new(format!("{}_NOT_BEFORE", name), "i64", "0");
+                constants.push(not_before.doc(format!("Start of the validity of [`{}`]; 0, as it is empty.", name)));
+                let not_after = Constant::new(format!("{}_NOT_AFTER", name), "i64", "0");
+                constants.push(not_after.doc(format!("End of the validity of [`{}`]; 0, as it is empty.", name)));
+                let serial = Constant::new(format!("{}_SERIAL", name), "&str", "\"\"");
+                constants.push(serial.doc(format!("Serial number of [`{}`]; empty.", name)));
+            }
+        }
+    }
+
+    // The chain only references the certificates actually present, preserving its length
+    let count = Constant::new("CERTIFICATE_COUNT", "usize", certs.len().to_string());
+    constants.push(count.doc(format!("Number of certificates in the chain of {}.", owner)));
+    let chain = (1..=certs.len()).map(|i| format!("CERTIFICATE_{}", i)).collect();
+    let chain = Constant::with_value("CERTIFICATE_CHAIN", "&[&[u8]]", Value::List(chain));
+    constants.push(chain.doc(format!("The chain of {}, leaf first.", owner)));
+
+    // The ends of the chain by role, so pinning the root does not depend on the chain
+    // length. Chains are leaf first, so a single certificate is both.
+    let (leaf, root) = match certs.len() {
+        0 => ("&[]".to_string(), "&[]".to_string()),
+        len => ("CERTIFICATE_1".to_string(), format!("CERTIFICATE_{}", len)),
+    };
+    let leaf = Constant::new("LEAF_CERTIFICATE", "&[u8]", leaf);
+    constants.push(leaf.doc("The leaf, first certificate of the chain; empty without a chain."));
+    let root = Constant::new("ROOT_CERTIFICATE", "&[u8]", root);
+    constants.push(root.doc("The root, last certificate of the chain; empty without a chain."));
+
+    // Named curve of the leaf certificate's key, empty if unknown
+    #[cfg(feature = "x509")]
+    if algorithm == KeyAlgorithm::Ecdsa {
+        let curve = certs.first().and_then(|leaf| crate::certificate::ec_curve(leaf).ok().flatten());
+        let curve = Constant::new("CURVE", "&str", format!("{:?}", curve.unwrap_or_default()));
+        constants.push(curve.doc("Named curve of the leaf certificate's key, empty if unknown."));
+    }
+
+    // Public key as an uncompressed point and as its X and Y coordinates. Keys that
+    // passed parsing under `verify` are P-256, so the coordinates are 32 bytes each.
+    #[cfg(feature = "verify")]
+    if algorithm == KeyAlgorithm::Ecdsa {
+        let (public_key, x, y) = match key.and_then(|key| crate::certificate::ec_public_key(key).ok().flatten()) {
+            Some(point) => (
+                Constant::bytes("PUBLIC_KEY", "&[u8]", &point, true),
+                Constant::bytes("PUBLIC_KEY_X", "[u8; 32]", &point[1..33], false),
+                Constant::bytes("PUBLIC_KEY_Y", "[u8; 32]", &point[33..], false),
+            ),
+            None => (
+                Constant::new("PUBLIC_KEY", "&[u8]", "&[]"),
+                Constant::new("PUBLIC_KEY_X", "[u8; 32]", "[0; 32]"),
+                Constant::new("PUBLIC_KEY_Y", "[u8; 32]", "[0; 32]"),
+            ),
+        };
+        let doc = format!("Public key of {} as an uncompressed SEC1 point, empty if unknown.", owner);
+        constants.push(public_key.doc(doc));
+        constants.push(x.doc("X coordinate of [`PUBLIC_KEY`], all zero if unknown."));
+        constants.push(y.doc("Y coordinate of [`PUBLIC_KEY`], all zero if unknown."));
+    }
+
+    // Subject of the leaf certificate, e.g. for logging which device a keybox belongs
+    // to when DeviceID is not set. Empty if there is no leaf or no Common Name.
+    #[cfg(feature = "x509")]
+    {
+        let leaf = certs.first();
+        let subject = leaf.and_then(|leaf| crate::certificate::subject(leaf).ok());
+        let cn = leaf.and_then(|leaf| crate::certificate::subject_common_name(leaf).ok().flatten());
+        let subject = Constant::new("CERTIFICATE_SUBJECT", "&str", format!("{:?}", subject.unwrap_or_default()));
+        constants.push(subject.doc("Subject of the leaf certificate, empty without a leaf."));
+        let cn = Constant::new("CERTIFICATE_SUBJECT_CN", "&str", format!("{:?}", cn.unwrap_or_default()));
+        constants.push(cn.doc("Common Name of the leaf certificate's subject, empty if it has none."));
+    }
+
+    // Key attestation extension of the leaf certificate, empty if it has none; the
+    // build script warns about that
+    #[cfg(feature = "x509")]
+    {
+        let leaf = certs.first();
+        let extension = match leaf.and_then(|leaf| crate::certificate::attestation_extension(leaf).ok().flatten()) {
+            Some(extension) => Constant::bytes("ATTESTATION_EXTENSION", "&[u8]", &extension, true),
+            None => Constant::new("ATTESTATION_EXTENSION", "&[u8]", "&[]"),
+        };
+        constants.push(extension.doc("Key attestation extension of the leaf certificate, empty if it has none."));
+    }
+
+    // Write the private key if it exists, otherwise an empty array
+    match private_key {
+        Some(private_key) => {
+            let label = key.and_then(|key| key.private_key_format.as_ref()).map(|format| format.label().to_string());
+            let what = label.map_or(String::new(), |label| format!(" ({})", label));
+            let doc = [format!("DER of the private key of {}{}.", owner, what)];
+            push_bytes(&mut constants, "PRIVATE_KEY", private_key, der_files, &doc)?
+        }
+        None => {
+            let doc = format!("DER of the private key of {}; empty.", owner);
+            push_empty_bytes(&mut constants, "PRIVATE_KEY", &doc)
+        }
+    }
+
+    Ok(constants)
+}
+
+// Lists the certificates and private key of one key module as `(name, path)` pairs,
+// e.g. `("ec_cert_1", "ec::CERTIFICATE_1")`. Only what the keybox contains is listed.
+fn key_artifacts(module: &str, key: Option<&KeyEntry>) -> Vec<(String, String)> {
+    let Some(key) = key else {
+        return Vec::new();
+    };
+    let mut artifacts: Vec<(String, String)> = (1..=key.certificates.len())
+        .map(|i| (format!("{}_cert_{}", module, i), format!("{}::CERTIFICATE_{}", module, i)))
+        .collect();
+    if key.private_key.is_some() {
+        artifacts.push((format!("{}_private_key", module), format!("{}::PRIVATE_KEY", module)));
+    }
+    artifacts
+}
+
+// Names of the constants that existed as flat `EC_` constants before they were
+// grouped into modules, and so keep deprecated aliases
+const FLAT_NAMES: &[&str] = &["CERTIFICATE_1", "CERTIFICATE_2", "CERTIFICATE_3", "PRIVATE_KEY"];
+
+// Writes `pub mod <module>` holding `constants`, with `DEVICE_ID` referring to the
+// top-level `device_id` constant. With `alias_prefix`, deprecated flat aliases such
+// as `EC_CERTIFICATE_1` follow for the constants in `FLAT_NAMES`, for code written
+// before the constants were grouped.
+fn write_module<W: Write>(
+    output_file: &mut W,
+    module: &str,
+    device_id: &str,
+    constants: &[Constant],
+    alias_prefix: Option<&str>,
+    options: &CodegenOptions,
+) -> io::Result<()> {
+    writeln!(output_file, "\npub mod {} {{", module)?;
+    writeln!(output_file, "    /// `DeviceID` of the keybox, empty if it has none.")?;
+    writeln!(output_file, "    pub const DEVICE_ID: &str = super::{};", device_id)?;
+    for constant in constants {
+        writeln!(output_file, "\n{}", constant.to_source("    ", options.bytes_per_line))?;
+    }
+    writeln!(output_file, "}}")?;
+
+    let Some(prefix) = alias_prefix else {
+        return Ok(());
+    };
+    for constant in constants.iter().filter(|constant| FLAT_NAMES.contains(&constant.name.as_str())) {
+        writeln!(output_file, "\n#[deprecated(note = \"use {}::{}\")]", module, constant.name)?;
+        let path = format!("{}::{}", module, constant.name);
+        let alias = Constant::new(format!("{}_{}", prefix, constant.name), constant.ty.clone(), path);
+        writeln!(output_file, "{}", alias.to_source("", None))?;
+    }
+
+    Ok(())
+}
+
+// Writes compile-time assertions that each certificate of a key's chain, at least the
+// first, and its private key are non-empty, so that including constants generated
+// without a key fails to compile
+fn write_assertions<W: Write>(output_file: &mut W, module: &str, key: Option<&KeyEntry>) -> io::Result<()> {
+    let certificates = key.map_or(0, |key| key.certificates.len()).max(1);
+    let names = (1..=certificates).map(|i| format!("CERTIFICATE_{}", i)).chain(["PRIVATE_KEY".to_string()]);
+    writeln!(output_file)?;
+    for name in names {
+        let path = format!("{}::{}", module, name);
+        let (condition, message) = (format!("!{}.is_empty()", path), format!("\"{} is empty\"", path));
+        let line = format!("const _: () = assert!({}, {});", condition, message);
+        // Laid out as rustfmt would, one argument per line when the line or the
+        // arguments are too long
+        if line.len() <= MAX_WIDTH && condition.len() + message.len() + 2 <= 60 {
+            writeln!(output_file, "{}", line)?;
+        } else {
+            writeln!(output_file, "const _: () = assert!(\n    {},\n    {}\n);", condition, message)?;
+        }
+    }
+    Ok(())
+}
+
+/// Which algorithms to emit constants for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithms {
+    Ecdsa,
+    Rsa,
+    #[default]
+    Both,
+}
+
+impl Algorithms {
+    fn includes_ecdsa(self) -> bool {
+        self != Algorithms::Rsa
+    }
+
+    fn includes_rsa(self) -> bool {
+        self != Algorithms::Ecdsa
+    }
+}
+
+impl FromStr for Algorithms {
+    type Err = KeyboxError;
+
+    /// Parses `ecdsa`, `rsa` or `both`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ecdsa" => Ok(Algorithms::Ecdsa),
+            "rsa" => Ok(Algorithms::Rsa),
+            "both" => Ok(Algorithms::Both),
+            other => Err(KeyboxError::Config(format!("unknown algorithm selection {:?}", other))),
+        }
+    }
+}
+
+/// The keybox file constants are generated from, recorded in the generated file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboxSource {
+    /// Where the keybox was read from, e.g. its path.
+    pub origin: String,
+    /// SHA-256 of the keybox file as read.
+    pub sha256: [u8; 32],
+}
+
+impl KeyboxSource {
+    /// Describes the keybox file read from `origin` with `contents`.
+    pub fn new(origin: impl Into<String>, contents: &[u8]) -> Self {
+        KeyboxSource {
+            origin: origin.into(),
+            sha256: Sha256::digest(contents).into(),
+        }
+    }
+}
+
+/// Options controlling the generated Rust constants.
+#[derive(Debug, Clone, Default)]
+pub struct CodegenOptions {
+    pub algorithms: Algorithms,
+    /// Bytes per line in generated byte arrays. `None` fits as many as rustfmt
+    /// would, so formatting the generated file leaves it unchanged.
+    pub bytes_per_line: Option<usize>,
+    /// Keybox file to record in the generated file, if known.
+    pub source: Option<KeyboxSource>,
+    /// Generation time to record, in seconds since the Unix epoch. Left unset
+    /// by default so that generating twice gives the same file.
+    pub timestamp: Option<u64>,
+    /// Prefix for every top-level name, e.g. `PIXEL6` for `PIXEL6_DEVICE_ID`,
+    /// `PIXEL6_EC_CERTIFICATE_1` and the `pixel6_ec` module, so that constants
+    /// for several keyboxes can be included side by side.
+    pub const_prefix: Option<String>,
+    /// Emit `const` assertions that every certificate and private key of the
+    /// selected algorithms is non-empty, for builds that must embed a keybox.
+    pub assert_populated: bool,
+}
+
+/// Writes the constants for `keybox` as Rust source to `output_file`.
+///
+/// Each algorithm gets its own module, e.g. `ec::CERTIFICATE_CHAIN` and
+/// `rsa::PRIVATE_KEY`, holding its first key. Further keys of the same
+/// algorithm go in `ec_2`, `ec_3` and so on. The older flat names
+/// `EC_CERTIFICATE_1` to `EC_CERTIFICATE_3` and `EC_PRIVATE_KEY` are still
+/// emitted as deprecated aliases. With the
+/// `artifacts` feature, `KEYBOX_ARTIFACTS` also lists every embedded
+/// certificate and private key by name, e.g. `("ec_cert_1", ec::CERTIFICATE_1)`,
+/// and with `hex` each certificate is also given as lowercase hex, e.g.
+/// `ec::CERTIFICATE_1_HEX`. With `arrays`, each certificate and private key
+/// is also a `[u8; N]` constant such as `ec::CERTIFICATE_1_ARRAY`, which the
+/// `&[u8]` constant refers to. With [`CodegenOptions::assert_populated`], an
+/// empty certificate or private key is a compile error in the including crate.
+/// Each module's `KEY_ALGORITHM` is a [`KeyAlgorithm`] named as
+/// `keybox_parser::KeyAlgorithm`, so the including crate must depend on this one.
+///
+/// The header and the `KEYBOX_SOURCE`, `KEYBOX_SHA256_HEX`, `GENERATOR_VERSION`
+/// and `GENERATED_AT` constants record the [`KeyboxSource`] and timestamp from
+/// `options` and the crate version, with empty strings and 0 for what is unset.
+/// `KEYBOX_PRESENT` is true if a source is set, so code can tell constants left
+/// empty for want of a keybox from those of a keybox without keys.
+pub fn write_rust_constants<W: Write>(output_file: &mut W, keybox: &Keybox, options: &CodegenOptions) -> io::Result<()> {
+    write_constants(output_file, keybox, options, None)
+}
+
+// Writes the constants, with certificates and private keys in `.der` files in
+// `der_dir` if given
+fn write_constants<W: Write>(
+    output_file: &mut W,
+    keybox: &Keybox,
+    options: &CodegenOptions,
+    der_dir: Option<&Path>,
+) -> io::Result<()> {
+    write_header(output_file, options)?;
+    // With a prefix, every top-level constant and module name starts with it
+    let prefix = options.const_prefix.as_deref().map(|prefix| prefix.trim_end_matches('_')).filter(|p| !p.is_empty());
+    write_keybox(output_file, keybox, options, prefix, der_dir)
+}
+
+// Turns a DeviceID into a name prefix, e.g. `pixel-6` into `PIXEL_6`, after
+// `const_prefix` if there is one. Ids starting with a digit get a `DEVICE_` prefix.
+pub(crate) fn device_prefix(const_prefix: Option<&str>, device_id: &str) -> String {
+    let mut name: String =
+        device_id.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert_str(0, "DEVICE_");
+    }
+    match const_prefix.map(|prefix| prefix.trim_end_matches('_')).filter(|p| !p.is_empty()) {
+        Some(prefix) => format!("{}_{}", prefix, name),
+        None => name,
+    }
+}
+
+/// Writes the constants for several keyboxes side by side to `output_file`,
+/// each under a prefix made from its `DeviceID`, e.g. `PIXEL_6_DEVICE_ID` and
+/// `pixel_6_ec::CERTIFICATE_1` for `pixel-6`. A
+/// [`CodegenOptions::const_prefix`] goes before each of them.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if a keybox has no `DeviceID`
+/// or two of them would get the same prefix.
+pub fn write_device_constants<W: Write>(
+    output_file: &mut W,
+    keyboxes: &[Keybox],
+    options: &CodegenOptions,
+) -> io::Result<()> {
+    write_devices(output_file, &by_device_id(keyboxes, options)?, options, None)
+}
+
+/// A keybox to write with [`write_named_constants`] under a name of its own
+/// rather than its `DeviceID`.
+#[derive(Debug, Clone)]
+pub struct NamedKeybox {
+    /// Name the prefix is made from, e.g. the directory the keybox was read from.
+    pub name: String,
+    pub keybox: Keybox,
+    /// Keybox file to record for this keybox in place of
+    /// [`CodegenOptions::source`], which is recorded if this is `None`.
+    pub source: Option<KeyboxSource>,
+}
+
+/// Writes the constants for several keyboxes side by side to `output_file`
+/// as [`write_device_constants`] does, but with each prefix made from the
+/// keybox's [`NamedKeybox::name`], so keyboxes without a `DeviceID` or with
+/// the same one can be written together.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if two names would give the
+/// same prefix.
+pub fn write_named_constants<W: Write>(
+    output_file: &mut W,
+    keyboxes: &[NamedKeybox],
+    options: &CodegenOptions,
+) -> io::Result<()> {
+    write_devices(output_file, &by_name(keyboxes, options), options, None)
+}
+
+// A keybox to write side by side with others: its name, the keybox and the source
+// to record
+type Device<'a> = (&'a str, &'a Keybox, Option<&'a KeyboxSource>);
+
+fn by_device_id<'a>(keyboxes: &'a [Keybox], options: &'a CodegenOptions) -> io::Result<Vec<Device<'a>>> {
+    let mut devices = Vec::new();
+    for (i, keybox) in keyboxes.iter().enumerate() {
+        let Some(device_id) = keybox.device_id.as_deref() else {
+            let message = format!("keybox {} has no DeviceID to name its constants by", i + 1);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        };
+        devices.push((device_id, keybox, options.source.as_ref()));
+    }
+    Ok(devices)
+}
+
+fn by_name<'a>(keyboxes: &'a [NamedKeybox], options: &'a CodegenOptions) -> Vec<Device<'a>> {
+    let source = |named: &'a NamedKeybox| named.source.as_ref().or(options.source.as_ref());
+    keyboxes.iter().map(|named| (named.name.as_str(), &named.keybox, source(named))).collect()
+}
+
+// Writes each keybox under its device prefix, after checking all the prefixes so
+// that a bad one leaves nothing half written
+fn write_devices<W: Write>(
+    output_file: &mut W,
+    devices: &[Device],
+    options: &CodegenOptions,
+    der_dir: Option<&Path>,
+) -> io::Result<()> {
+    let mut prefixes: Vec<(String, &str)> = Vec::new();
+    for (name, _, _) in devices {
+        let prefix = device_prefix(options.const_prefix.as_deref(), name);
+        if let Some((_, other)) = prefixes.iter().find(|(seen, _)| *seen == prefix) {
+            let message = format!("{:?} and {:?} both give prefix {}", other, name, prefix);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        prefixes.push((prefix, name));
+    }
+
+    write_header(output_file, options)?;
+    for (i, ((_, keybox, source), (prefix, _))) in devices.iter().zip(&prefixes).enumerate() {
+        if i > 0 {
+            writeln!(output_file)?;
+        }
+        let options = CodegenOptions {
+            source: source.cloned(),
+            ..options.clone()
+        };
+        write_keybox(output_file, keybox, &options, Some(prefix), der_dir)?;
+    }
+    Ok(())
+}
+
+// Writes the comment at the top of a generated file
+fn write_header<W: Write>(output_file: &mut W, options: &CodegenOptions) -> io::Result<()> {
+    writeln!(output_file, "// Auto-generated constants")?;
+    if let Some(source) = &options.source {
+        writeln!(output_file, "// Source: {}", source.origin.escape_debug())?;
+        writeln!(output_file, "// SHA-256: {}", hex(&source.sha256))?;
+    }
+    match options.timestamp {
+        Some(timestamp) => writeln!(output_file, "// Generated by keybox_parser {} at {}", VERSION, timestamp)?,
+        None => writeln!(output_file, "// Generated by keybox_parser {}", VERSION)?,
+    }
+    writeln!(output_file, "//")?;
+    writeln!(output_file, "// Include with: include!(concat!(env!(\"OUT_DIR\"), \"/ec_constants.rs\"));\n")
+}
+
+// Writes the constants of one keybox, with every top-level name starting with
+// `prefix` if given
+fn write_keybox<W: Write>(
+    output_file: &mut W,
+    keybox: &Keybox,
+    options: &CodegenOptions,
+    prefix: Option<&str>,
+    der_dir: Option<&Path>,
+) -> io::Result<()> {
+    if let Some(prefix) = prefix.filter(|prefix| !is_identifier(prefix)) {
+        let message = format!("constant prefix {:?} is not an identifier", prefix);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    let top = |name: &str| prefix.map_or(name.to_string(), |prefix| format!("{}_{}", prefix.to_uppercase(), name));
+
+    let device_id = format!("{:?}", keybox.device_id.as_deref().unwrap_or(""));
+    let device_id =
+        Constant::new(top("DEVICE_ID"), "&str", device_id).doc("`DeviceID` of the keybox, empty if it has none.");
+    writeln!(output_file, "{}", device_id.to_source("", None))?;
+    // How the keys are named in doc comments, e.g. `ECDSA key 2 of keybox "pixel-6"`
+    let keybox_name = keybox.device_id.as_ref().map_or("the keybox".to_string(), |id| format!("keybox {:?}", id));
+    let owner = |algorithm: KeyAlgorithm, n: usize| {
+        format!("{} key {} of {}", algorithm.as_str().to_uppercase(), n, keybox_name)
+    };
+
+    // Which keybox and generator produced the file, for tracing what a binary embeds
+    let source = options.source.as_ref();
+    let origin = source.map_or("", |source| &source.origin);
+    let sha256 = source.map_or(String::new(), |source| hex(&source.sha256));
+    let metadata = [
+        Constant::new(top("KEYBOX_SOURCE"), "&str", format!("{:?}", origin))
+            .doc("Where the keybox was read from, empty if unknown."),
+        Constant::new(top("KEYBOX_SHA256_HEX"), "&str", format!("{:?}", sha256))
+            .doc("SHA-256 of the keybox file as read, as lowercase hex; empty if unknown."),
+        Constant::new(top("KEYBOX_PRESENT"), "bool", source.is_some().to_string())
+            .doc("Whether these constants were generated from a keybox rather than left empty."),
+        Constant::new(top("GENERATOR_VERSION"), "&str", format!("{:?}", VERSION))
+            .doc("Version of keybox_parser that generated these constants."),
+        Constant::new(top("GENERATED_AT"), "u64", options.timestamp.unwrap_or(0).to_string())
+            .doc("When these constants were generated, as a Unix timestamp; 0 if not recorded."),
+    ];
+    for constant in metadata {
+        writeln!(output_file, "{}", constant.to_source("", None))?;
+    }
+
+    // Name and constant path of every certificate and private key written, for
+    // KEYBOX_ARTIFACTS
+    let mut artifacts = Vec::new();
+
+    let selected = [
+        (KeyAlgorithm::Ecdsa, options.algorithms.includes_ecdsa()),
+        (KeyAlgorithm::Rsa, options.algorithms.includes_rsa()),
+    ];
+    for (algorithm, _) in selected.into_iter().filter(|(_, included)| *included) {
+        // The first key keeps the `ec`/`rsa` module, so a keybox with one key per
+        // algorithm generates the same constants as before keys were split
+        let alias_prefix = top(algorithm.prefix());
+        let module = alias_prefix.to_lowercase();
+        let mut keys = keybox.keys_for(algorithm);
+        let first = keys.next();
+        let der_files = der_dir.map(|dir| DerFiles { dir, module: &module });
+        let constants = key_constants(algorithm, first, &owner(algorithm, 1), der_files.as_ref())?;
+        // Only the ECDSA constants were flat before
+        let alias_prefix = Some(alias_prefix.as_str()).filter(|_| algorithm == KeyAlgorithm::Ecdsa);
+        write_module(output_file, &module, &top("DEVICE_ID"), &constants, alias_prefix, options)?;
+        if options.assert_populated {
+            write_assertions(output_file, &module, first)?;
+        }
+        artifacts.extend(key_artifacts(&module, first));
+
+        // Further keys of the same algorithm get numbered modules, e.g. `ec_2`
+        for (i, key) in keys.enumerate() {
+            let module = format!("{}_{}", module, i + 2);
+            let der_files = der_dir.map(|dir| DerFiles { dir, module: &module });
+            let constants = key_constants(algorithm, Some(key), &owner(algorithm, i + 2), der_files.as_ref())?;
+            write_module(output_file, &module, &top("DEVICE_ID"), &constants, None, options)?;
+            if options.assert_populated {
+                write_assertions(output_file, &module, Some(key))?;
+            }
+            artifacts.extend(key_artifacts(&module, Some(key)));
+        }
+    }
+
+    if cfg!(feature = "artifacts") {
+        let entries: String = artifacts
+            .iter()
+            .map(|(name, path)| format!("    ({:?}, {}),\n", name, path))
+            .collect();
+        let value = if entries.is_empty() { "&[]".to_string() } else { format!("&[\n{}]", entries) };
+        writeln!(output_file, "\n/// Every embedded certificate and private key, by name.")?;
+        writeln!(output_file, "pub const {}: &[(&str, &[u8])] = {};", top("KEYBOX_ARTIFACTS"), value)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the constants for `keybox` to the file at `file_path`, replacing it
+/// and creating its parent directories if needed. Errors name the path that
+/// could not be written.
+///
+/// With the `der-files` feature, each certificate and private key is written
+/// to a `.der` file in the same directory, e.g. `ec_certificate_1.der`, and
+/// included with a relative `include_bytes!`.
+pub fn write_rust_constants_file(file_path: &Path, keybox: &Keybox, options: &CodegenOptions) -> io::Result<()> {
+    write_file(file_path, |output_file, der_dir| write_constants(output_file, keybox, options, der_dir))
+}
+
+/// Writes the constants for several keyboxes to the file at `file_path`, as
+/// [`write_device_constants`] does and otherwise like
+/// [`write_rust_constants_file`].
+pub fn write_device_constants_file(file_path: &Path, keyboxes: &[Keybox], options: &CodegenOptions) -> io::Result<()> {
+    let devices = by_device_id(keyboxes, options)?;
+    write_file(file_path, |output_file, der_dir| write_devices(output_file, &devices, options, der_dir))
+}
+
+/// Writes the constants for several named keyboxes to the file at
+/// `file_path`, as [`write_named_constants`] does and otherwise like
+/// [`write_rust_constants_file`].
+pub fn write_named_constants_file(
+    file_path: &Path,
+    keyboxes: &[NamedKeybox],
+    options: &CodegenOptions,
+) -> io::Result<()> {
+    let devices = by_name(keyboxes, options);
+    write_file(file_path, |output_file, der_dir| write_devices(output_file, &devices, options, der_dir))
+}
+
+// Creates `file_path` and its parent directories and lets `write` fill it in, passing
+// the directory for `.der` files with the `der-files` feature
+fn write_file<F>(file_path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>, Option<&Path>) -> io::Result<()>,
+{
+    let with_path =
+        |path: &Path, e: io::Error| io::Error::new(e.kind(), format!("cannot write {}: {}", path.display(), e));
+    if let Some(parent) = file_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| with_path(parent, e))?;
+    }
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(file_path);
+    let mut output_file = BufWriter::new(file.map_err(|e| with_path(file_path, e))?);
+    // With `der-files`, certificates and private keys go in `.der` files next to the
+    // generated file instead of inline arrays, which are slow to compile for big chains
+    let der_dir = file_path.parent().filter(|_| cfg!(feature = "der-files"));
+    write(&mut output_file, der_dir)?;
+    output_file.flush().map_err(|e| with_path(file_path, e))
+}
@@ -0,0 +1,145 @@
+// src/error.rs
+use alloc::string::String;
+use core::fmt;
+
+// Variants and impls needing std are left out of the library when build.rs sets
+// `keybox_no_std`, i.e. without the std feature. The build script shares this file
+// and never sets it, since it always runs with std.
+
+/// Errors that can occur while locating or parsing a keybox.
+#[derive(Debug)]
+pub enum KeyboxError {
+    /// The keybox could not be read, including when the file does not exist.
+    #[cfg(not(keybox_no_std))]
+    Io(std::io::Error),
+    /// The keybox is not well-formed XML. `line` and `column` count from 1, and
+    /// `context` names the keybox element being read, if any.
+    #[cfg(not(keybox_no_std))]
+    Xml {
+        line: u64,
+        column: u64,
+        context: Option<String>,
+        source: xml::reader::Error,
+    },
+    /// A certificate or private key body is not valid base64. `length` is the
+    /// length of the body with whitespace removed, which offsets in `source`
+    /// refer to.
+    Base64Decode {
+        context: String,
+        length: usize,
+        source: base64::DecodeError,
+    },
+    /// A decoded certificate is not a valid DER X.509 certificate.
+    InvalidCertificate { context: String, reason: String },
+    /// A decoded private key could not be parsed.
+    InvalidPrivateKey { context: String, reason: String },
+    /// A private key does not match the public key of its leaf certificate.
+    KeyMismatch { context: String },
+    /// Keybox JSON could not be parsed.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    /// `KEYBOX_PATH` was not set and there is no keybox in the default
+    /// directory; see [`default_keybox_path`](crate::default_keybox_path).
+    MissingKeyboxPath,
+    /// The XML is well-formed but does not describe a valid keybox.
+    MalformedKeybox(String),
+    /// The build was configured with an invalid or unsupported option.
+    Config(String),
+}
+
+impl fmt::Display for KeyboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(keybox_no_std))]
+            KeyboxError::Io(e) => write!(f, "failed to read keybox: {}", e),
+            #[cfg(not(keybox_no_std))]
+            KeyboxError::Xml {
+                line,
+                column,
+                context,
+                source,
+            } => {
+                write!(f, "failed to parse keybox XML at line {}, column {}", line, column)?;
+                if let Some(context) = context {
+                    write!(f, " in {}", context)?;
+                }
+                write!(f, ": {}", source.msg())
+            }
+            KeyboxError::Base64Decode { context, length, source } => {
+                write!(f, "failed to decode {} ({} base64 characters): {}", context, length, source)
+            }
+            KeyboxError::InvalidCertificate { context, reason } => {
+                write!(f, "invalid {}: {}", context, reason)
+            }
+            KeyboxError::InvalidPrivateKey { context, reason } => {
+                write!(f, "invalid {}: {}", context, reason)
+            }
+            KeyboxError::KeyMismatch { context } => {
+                write!(f, "{} does not match its leaf certificate", context)
+            }
+            #[cfg(feature = "json")]
+            KeyboxError::Json(e) => write!(f, "failed to parse keybox JSON: {}", e),
+            KeyboxError::MissingKeyboxPath => {
+                write!(f, "KEYBOX_PATH is not set and there is no keybox under $ANDROID_BUILD_TOP/vendor/keybox")
+            }
+            KeyboxError::MalformedKeybox(reason) => write!(f, "malformed keybox: {}", reason),
+            KeyboxError::Config(reason) => write!(f, "invalid configuration: {}", reason),
+        }
+    }
+}
+
+#[cfg(not(keybox_no_std))]
+impl std::error::Error for KeyboxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KeyboxError::Io(e) => Some(e),
+            KeyboxError::Xml { source, .. } => Some(source),
+            KeyboxError::Base64Decode { source, .. } => Some(source),
+            #[cfg(feature = "json")]
+            KeyboxError::Json(e) => Some(e),
+            KeyboxError::InvalidCertificate { .. }
+            | KeyboxError::InvalidPrivateKey { .. }
+            | KeyboxError::KeyMismatch { .. }
+            | KeyboxError::MissingKeyboxPath
+            | KeyboxError::MalformedKeybox(_)
+            | KeyboxError::Config(_) => None,
+        }
+    }
+}
+
+#[cfg(not(keybox_no_std))]
+impl From<std::io::Error> for KeyboxError {
+    fn from(e: std::io::Error) -> Self {
+        KeyboxError::Io(e)
+    }
+}
+
+#[cfg(not(keybox_no_std))]
+impl KeyboxError {
+    // Wraps an XML error with its position and the element being read
+    pub(crate) fn xml(source: xml::reader::Error, context: Option<String>) -> Self {
+        use xml::common::Position;
+
+        let position = source.position();
+        KeyboxError::Xml {
+            line: position.row + 1,
+            column: position.column + 1,
+            context,
+            source,
+        }
+    }
+}
+
+#[cfg(not(keybox_no_std))]
+impl From<xml::reader::Error> for KeyboxError {
+    fn from(e: xml::reader::Error) -> Self {
+        KeyboxError::xml(e, None)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for KeyboxError {
+    fn from(e: serde_json::Error) -> Self {
+        KeyboxError::Json(e)
+    }
+}
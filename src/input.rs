@@ -0,0 +1,45 @@
+// src/input.rs
+use std::io::{Cursor, Read};
+
+use crate::error::KeyboxError;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+// Reads up to `len` bytes from the start of `reader` and returns them along with a
+// reader that still yields the whole input.
+fn peek<'a, R: Read + 'a>(mut reader: R, len: usize) -> Result<(Vec<u8>, Box<dyn Read + 'a>), KeyboxError> {
+    let mut prefix = Vec::with_capacity(len);
+    (&mut reader).take(len as u64).read_to_end(&mut prefix)?;
+    let rest = Cursor::new(prefix.clone()).chain(reader);
+    Ok((prefix, Box::new(rest)))
+}
+
+// Skips a leading UTF-8 byte order mark, which some Windows tools write and
+// EventReader rejects before the root element
+fn strip_bom<'a>(mut reader: Box<dyn Read + 'a>) -> Result<Box<dyn Read + 'a>, KeyboxError> {
+    let mut prefix = Vec::with_capacity(UTF8_BOM.len());
+    (&mut reader).take(UTF8_BOM.len() as u64).read_to_end(&mut prefix)?;
+    if prefix == UTF8_BOM {
+        return Ok(reader);
+    }
+    Ok(Box::new(Cursor::new(prefix).chain(reader)))
+}
+
+// Wraps the raw keybox input so that gzip-compressed keyboxes are transparently
+// decompressed and a leading BOM is dropped. Anything else is passed through as
+// plain XML.
+pub(crate) fn open_input<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>, KeyboxError> {
+    let (prefix, reader) = peek(reader, GZIP_MAGIC.len())?;
+    if prefix != GZIP_MAGIC {
+        return strip_bom(reader);
+    }
+
+    #[cfg(feature = "gzip")]
+    return strip_bom(Box::new(flate2::read::GzDecoder::new(reader)));
+
+    #[cfg(not(feature = "gzip"))]
+    Err(KeyboxError::MalformedKeybox(
+        "keybox is gzip-compressed but the gzip feature is not enabled".to_string(),
+    ))
+}
@@ -0,0 +1,164 @@
+// src/json.rs
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::error::KeyboxError;
+use crate::model::{
+    wrap_private_key, BodyEncoding, KeyAlgorithm, KeyEntry, Keybox, KeyboxSignature, PrivateKeyFormat,
+};
+use crate::pem::decode_base64;
+
+fn key_to_json(key: &KeyEntry) -> Value {
+    json!({
+        "algorithm": key.algorithm.as_str(),
+        "declared_algorithm": key.declared_algorithm,
+        "certificates": key.certificates.iter().map(|bytes| STANDARD.encode(bytes)).collect::<Vec<String>>(),
+        "private_key": key.private_key.as_ref().map(|bytes| STANDARD.encode(bytes)),
+        "extra_private_keys": key.extra_private_keys.iter().map(|bytes| STANDARD.encode(bytes)).collect::<Vec<_>>(),
+        "private_key_format": key.private_key_format.as_ref().map(PrivateKeyFormat::label),
+        "encoding": key.encoding.map(BodyEncoding::as_str),
+        "padding_repaired": key.padding_repaired,
+        "url_safe_base64": key.url_safe_base64,
+        "attributes": key.attributes,
+    })
+}
+
+/// Serializes `keybox` to JSON, with certificates and private keys encoded as
+/// base64 DER, private key formats given by their PEM label and each key's
+/// `algorithm` attribute kept verbatim as `declared_algorithm`.
+///
+/// ```json
+/// {
+///   "device_id": "...",
+///   "keys": [
+///     {
+///       "algorithm": "ecdsa",
+///       "declared_algorithm": "ECDSA",
+///       "certificates": ["MII..."],
+///       "private_key": "MHc...",
+///       "extra_private_keys": [],
+///       "private_key_format": "EC PRIVATE KEY",
+///       "encoding": "pem",
+///       "padding_repaired": false,
+///       "url_safe_base64": false,
+///       "attributes": { "purpose": "attestation" }
+///     }
+///   ],
+///   "signature": { "algorithm": "...", "value": "..." },
+///   "format_version": "1.0"
+/// }
+/// ```
+///
+/// `signature` is `null` when the keybox has no `<Signature>` element, and
+/// `format_version` when no version was declared.
+pub fn to_json(keybox: &Keybox) -> String {
+    let value = json!({
+        "device_id": keybox.device_id,
+        "keys": keybox.keys.iter().map(key_to_json).collect::<Vec<Value>>(),
+        "signature": keybox.signature.as_ref().map(|signature| json!({
+            "algorithm": signature.algorithm,
+            "value": STANDARD.encode(&signature.value),
+        })),
+        "format_version": keybox.format_version,
+    });
+    serde_json::to_string_pretty(&value).unwrap()
+}
+
+fn malformed(reason: &str) -> KeyboxError {
+    KeyboxError::MalformedKeybox(format!("invalid keybox JSON: {}", reason))
+}
+
+fn optional_string<'a>(value: &'a Value, field: &str) -> Result<Option<&'a str>, KeyboxError> {
+    match &value[field] {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s)),
+        _ => Err(malformed(&format!("{} must be a string", field))),
+    }
+}
+
+/// Reconstructs a [`Keybox`] from JSON produced by [`to_json`].
+pub fn from_json(json: &str) -> Result<Keybox, KeyboxError> {
+    let value: Value = serde_json::from_str(json)?;
+    let mut keybox = Keybox {
+        device_id: optional_string(&value, "device_id")?.map(str::to_string),
+        format_version: optional_string(&value, "format_version")?.map(str::to_string),
+        ..Keybox::default()
+    };
+
+    let keys = value["keys"].as_array().ok_or_else(|| malformed("keys must be an array"))?;
+    for key in keys {
+        let mut entry = match key["algorithm"].as_str() {
+            Some("ecdsa") => KeyEntry::new(KeyAlgorithm::Ecdsa),
+            Some("rsa") => KeyEntry::new(KeyAlgorithm::Rsa),
+            Some(other) => return Err(malformed(&format!("unknown algorithm {:?}", other))),
+            None => return Err(malformed("algorithm must be a string")),
+        };
+        let prefix = entry.algorithm.prefix();
+
+        if let Some(declared) = optional_string(key, "declared_algorithm")? {
+            entry.declared_algorithm = declared.to_string();
+        }
+        let encoded = key["certificates"].as_array().ok_or_else(|| malformed("certificates must be an array"))?;
+        for (i, cert) in encoded.iter().enumerate() {
+            let cert = cert.as_str().ok_or_else(|| malformed("certificates must be strings"))?;
+            entry.certificates.push(decode_base64(cert, format!("{} certificate {}", prefix, i + 1))?);
+        }
+        if let Some(private_key) = optional_string(key, "private_key")? {
+            let der = decode_base64(private_key, format!("{} private key", prefix))?;
+            entry.private_key = Some(wrap_private_key(der));
+        }
+        match &key["extra_private_keys"] {
+            Value::Null => {}
+            Value::Array(encoded) => {
+                for (i, private_key) in encoded.iter().enumerate() {
+                    let private_key =
+                        private_key.as_str().ok_or_else(|| malformed("extra_private_keys must be strings"))?;
+                    let context = format!("{} private key {}", prefix, i + 2);
+                    entry.extra_private_keys.push(wrap_private_key(decode_base64(private_key, context)?));
+                }
+            }
+            _ => return Err(malformed("extra_private_keys must be an array")),
+        }
+        entry.private_key_format = optional_string(key, "private_key_format")?.map(PrivateKeyFormat::from_label);
+        entry.encoding = match optional_string(key, "encoding")? {
+            None => None,
+            Some("pem") => Some(BodyEncoding::Pem),
+            Some("base64") => Some(BodyEncoding::Base64),
+            Some("mixed") => Some(BodyEncoding::Mixed),
+            Some(other) => return Err(malformed(&format!("unknown encoding {:?}", other))),
+        };
+        entry.padding_repaired = match &key["padding_repaired"] {
+            Value::Null => false,
+            Value::Bool(repaired) => *repaired,
+            _ => return Err(malformed("padding_repaired must be a boolean")),
+        };
+        entry.url_safe_base64 = match &key["url_safe_base64"] {
+            Value::Null => false,
+            Value::Bool(url_safe) => *url_safe,
+            _ => return Err(malformed("url_safe_base64 must be a boolean")),
+        };
+        match &key["attributes"] {
+            Value::Null => {}
+            Value::Object(attributes) => {
+                for (name, value) in attributes {
+                    let value = value.as_str().ok_or_else(|| malformed("attributes must be strings"))?;
+                    entry.attributes.insert(name.clone(), value.to_string());
+                }
+            }
+            _ => return Err(malformed("attributes must be an object")),
+        }
+        keybox.keys.push(entry);
+    }
+
+    let signature = &value["signature"];
+    if !signature.is_null() {
+        let encoded = optional_string(signature, "value")?.ok_or_else(|| malformed("signature value must be a string"))?;
+        keybox.signature = Some(KeyboxSignature {
+            algorithm: optional_string(signature, "algorithm")?.unwrap_or_default().to_string(),
+            value: decode_base64(encoded, "signature".to_string())?,
+        });
+    }
+
+    Ok(keybox)
+}
@@ -0,0 +1,113 @@
+// src/key.rs
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::error::KeyboxError;
+use crate::model::PrivateKeyFormat;
+
+// DER encodings of the algorithm OIDs used in PKCS#8 PrivateKeyInfo
+const ID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const RSA_ENCRYPTION: &[u8] = &[0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const DER_NULL: &[u8] = &[0x05, 0x00];
+
+fn invalid(reason: &str) -> KeyboxError {
+    KeyboxError::InvalidPrivateKey {
+        context: "private key".to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+pub(crate) fn encode_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        der.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|&b| b == 0).collect();
+        der.push(0x80 | bytes.len() as u8);
+        der.extend_from_slice(&bytes);
+    }
+    der.extend_from_slice(content);
+    der
+}
+
+// A DER element split off the front of a buffer
+struct Tlv<'a> {
+    tag: u8,
+    element: &'a [u8],
+    content: &'a [u8],
+    rest: &'a [u8],
+}
+
+fn read_tlv(der: &[u8]) -> Result<Tlv<'_>, KeyboxError> {
+    let (&tag, rest) = der.split_first().ok_or_else(|| invalid("truncated DER"))?;
+    let (&first, rest) = rest.split_first().ok_or_else(|| invalid("truncated DER"))?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > core::mem::size_of::<usize>() || rest.len() < count {
+            return Err(invalid("unsupported DER length"));
+        }
+        let len = rest[..count].iter().fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return Err(invalid("truncated DER"));
+    }
+    let header_len = der.len() - rest.len();
+    Ok(Tlv {
+        tag,
+        element: &der[..header_len + len],
+        content: &rest[..len],
+        rest: &rest[len..],
+    })
+}
+
+// Returns the DER-encoded curve OID from a SEC1 ECPrivateKey's `[0] parameters`
+fn sec1_curve(sec1: &[u8]) -> Result<&[u8], KeyboxError> {
+    let mut fields = read_tlv(sec1)?.content;
+    while !fields.is_empty() {
+        let field = read_tlv(fields)?;
+        if field.tag == 0xa0 {
+            return Ok(read_tlv(field.content)?.element);
+        }
+        fields = field.rest;
+    }
+    Err(invalid("SEC1 key does not name its curve"))
+}
+
+/// Converts a DER private key in `format` to PKCS#8 `PrivateKeyInfo`, so
+/// callers get a consistent encoding regardless of how the keybox stored it.
+///
+/// PKCS#8 keys are returned unchanged. SEC1 keys must name their curve.
+pub fn to_pkcs8(der: &[u8], format: &PrivateKeyFormat) -> Result<Vec<u8>, KeyboxError> {
+    let algorithm = match format {
+        PrivateKeyFormat::Pkcs8 => return Ok(der.to_vec()),
+        PrivateKeyFormat::Sec1 => [ID_EC_PUBLIC_KEY, sec1_curve(der)?].concat(),
+        PrivateKeyFormat::Pkcs1 => [RSA_ENCRYPTION, DER_NULL].concat(),
+        PrivateKeyFormat::Other(label) => {
+            return Err(invalid(&format!("cannot convert {:?} keys to PKCS#8", label)));
+        }
+    };
+
+    let version = [0x02, 0x01, 0x00];
+    let body = [&version[..], &encode_tlv(0x30, &algorithm), &encode_tlv(0x04, der)].concat();
+    Ok(encode_tlv(0x30, &body))
+}
+
+/// Guesses the format of a DER private key from its structure: SEC1 and PKCS#8
+/// keys start with a version followed by an OCTET STRING or an algorithm
+/// SEQUENCE, PKCS#1 keys with a version followed by the modulus. Returns `None`
+/// for anything else.
+pub fn detect_format(der: &[u8]) -> Option<PrivateKeyFormat> {
+    let outer = read_tlv(der).ok().filter(|outer| outer.tag == 0x30)?;
+    let version = read_tlv(outer.content).ok().filter(|version| version.tag == 0x02)?;
+    match read_tlv(version.rest).ok()?.tag {
+        0x04 => Some(PrivateKeyFormat::Sec1),
+        0x30 => Some(PrivateKeyFormat::Pkcs8),
+        0x02 => Some(PrivateKeyFormat::Pkcs1),
+        _ => None,
+    }
+}
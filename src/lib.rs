@@ -1,3 +1,59 @@
 // src/lib.rs
-// Include the generated constants
-pub mod ec_constants;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// Lets the generated constants name the crate's types as `keybox_parser::...`, as
+// they do in consumers
+extern crate self as keybox_parser;
+
+// Without the std feature only the model and the decoding helpers are built
+pub mod binary;
+#[cfg(feature = "std")]
+pub mod builder;
+#[cfg(feature = "std")]
+pub mod certificate;
+#[cfg(feature = "std")]
+pub mod check;
+#[cfg(feature = "std")]
+pub mod codegen;
+pub mod error;
+#[cfg(feature = "std")]
+mod input;
+pub mod key;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod model;
+#[cfg(feature = "std")]
+mod parser;
+pub mod pem;
+#[cfg(feature = "pkcs12")]
+pub mod pkcs12;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
+pub mod writer;
+
+pub use binary::{from_binary, to_binary};
+#[cfg(feature = "std")]
+pub use builder::KeyboxBuilder;
+pub use error::KeyboxError;
+pub use model::{
+    BodyEncoding, CertificateRole, KeyAlgorithm, KeyEntry, Keybox, KeyboxSet, KeyboxSignature, KeyboxStats,
+    MergePolicy, PrivateKeyBytes, PrivateKeyFormat, FORMAT_VERSION,
+};
+#[cfg(feature = "std")]
+pub use parser::{
+    default_keybox_path, device_keybox_paths, duplicate_device_ids, for_each_key, keybox_stats, parse_default_keybox,
+    parse_keybox, parse_keybox_bytes, parse_keybox_reader, parse_keybox_with, ParseOptions, ParsedKeyboxes,
+    DEFAULT_KEYBOX_DIR,
+};
+pub use pem::{der_to_base64, der_to_pem, pem_to_der, pem_to_der_all};
+#[cfg(feature = "pkcs12")]
+pub use pkcs12::to_unencrypted_pkcs12;
+#[cfg(feature = "std")]
+pub use writer::to_xml;
+
+// Include the constants generated by build.rs
+pub mod ec_constants {
+    include!(concat!(env!("OUT_DIR"), "/ec_constants.rs"));
+}
@@ -0,0 +1,854 @@
+// src/parser.rs
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::certificate::validate_certificate;
+use crate::error::KeyboxError;
+use crate::input::open_input;
+use crate::model::{
+    wrap_private_key, BodyEncoding, KeyAlgorithm, KeyEntry, Keybox, KeyboxSignature, KeyboxStats, PrivateKeyFormat,
+    FORMAT_VERSION,
+};
+use crate::pem::{clean_bodies, clean_body, decode_base64, decode_base64_repairing, pem_label, Repairs};
+
+// Decodes and validates one certificate, with `context` naming it in errors. The
+// repairs say what of its base64 had to be worked around.
+pub(crate) fn decode_certificate(cert: &str, context: &str) -> Result<(Vec<u8>, Repairs), KeyboxError> {
+    let (der, repairs) = decode_base64_repairing(cert, context.to_string())?;
+    validate_certificate(&der, context)?;
+    Ok((der, repairs))
+}
+
+// Rejects a private key whose PEM label names the other algorithm, e.g. an
+// `RSA PRIVATE KEY` block in an ECDSA key. PKCS#8 and unknown labels are not
+// tied to one algorithm and pass.
+#[cfg_attr(not(feature = "verify"), allow(dead_code))]
+pub(crate) fn check_private_key_label(
+    algorithm: KeyAlgorithm,
+    format: Option<&PrivateKeyFormat>,
+    context: &str,
+) -> Result<(), KeyboxError> {
+    let expected = match format {
+        Some(PrivateKeyFormat::Sec1) => KeyAlgorithm::Ecdsa,
+        Some(PrivateKeyFormat::Pkcs1) => KeyAlgorithm::Rsa,
+        _ => return Ok(()),
+    };
+    if expected == algorithm {
+        return Ok(());
+    }
+    Err(KeyboxError::InvalidPrivateKey {
+        context: context.to_string(),
+        reason: format!(
+            "PEM label {} does not match algorithm {}",
+            format.map_or("", PrivateKeyFormat::label),
+            algorithm.as_str()
+        ),
+    })
+}
+
+// Undecoded contents of a `<Key>` element with a recognized algorithm
+pub(crate) struct PendingKey {
+    pub(crate) algorithm: KeyAlgorithm,
+    pub(crate) declared_algorithm: String,
+    pub(crate) certs: Vec<String>,
+    pub(crate) private_key: Option<String>,
+    pub(crate) extra_private_keys: Vec<String>,
+    pub(crate) private_key_format: Option<PrivateKeyFormat>,
+    pub(crate) encoding: Option<BodyEncoding>,
+    pub(crate) attributes: BTreeMap<String, String>,
+}
+
+// Notes on `key` what had to be worked around to decode one of its bodies
+fn record_repairs(key: &mut KeyEntry, repairs: Repairs) {
+    key.padding_repaired |= repairs.padding;
+    key.url_safe_base64 |= repairs.url_safe;
+}
+
+impl PendingKey {
+    // `context` names the key in errors, e.g. `keybox 1 EC`. With `verify`, failed
+    // checks go to `on_problem` like mismatched counts. Of `options` only
+    // `verify_key_cert_match` and `keep_raw_base64` apply to a single key.
+    #[cfg_attr(not(feature = "verify"), allow(unused_variables))]
+    fn decode(
+        self,
+        context: &str,
+        options: &ParseOptions,
+        on_problem: &mut dyn FnMut(KeyboxError) -> Result<(), KeyboxError>,
+    ) -> Result<KeyEntry, KeyboxError> {
+        let mut key = KeyEntry {
+            declared_algorithm: self.declared_algorithm,
+            private_key_format: self.private_key_format,
+            encoding: self.encoding,
+            attributes: self.attributes,
+            ..KeyEntry::new(self.algorithm)
+        };
+        for (i, cert) in self.certs.iter().enumerate() {
+            let (der, repairs) = decode_certificate(cert, &format!("{} certificate {}", context, i + 1))?;
+            key.certificates.push(der);
+            record_repairs(&mut key, repairs);
+        }
+        if let Some(private_key) = &self.private_key {
+            let (der, repairs) = decode_base64_repairing(private_key, format!("{} private key", context))?;
+            key.private_key = Some(wrap_private_key(der));
+            record_repairs(&mut key, repairs);
+        }
+        // Further private keys are numbered after the first, e.g. `private key 2`
+        for (i, private_key) in self.extra_private_keys.iter().enumerate() {
+            let (der, repairs) = decode_base64_repairing(private_key, format!("{} private key {}", context, i + 2))?;
+            key.extra_private_keys.push(wrap_private_key(der));
+            record_repairs(&mut key, repairs);
+        }
+        if options.keep_raw_base64 {
+            key.raw_certificates = self.certs;
+        }
+
+        #[cfg(feature = "verify")]
+        check_private_key_label(key.algorithm, key.private_key_format.as_ref(), &format!("{} private key", context))
+            .or_else(&mut *on_problem)?;
+
+        #[cfg(feature = "verify")]
+        crate::certificate::verify_chain_order(&key.certificates, context).or_else(&mut *on_problem)?;
+
+        #[cfg(feature = "verify")]
+        if let (KeyAlgorithm::Ecdsa, Some(leaf)) = (key.algorithm, key.leaf()) {
+            crate::certificate::verify_p256(leaf, &format!("{} certificate 1", context)).or_else(&mut *on_problem)?;
+            if let (true, Some(private_key)) = (options.verify_key_cert_match, &key.private_key) {
+                crate::certificate::verify_ec_key_matches(private_key, leaf, &format!("{} private key", context))
+                    .or_else(&mut *on_problem)?;
+            }
+        }
+
+        Ok(key)
+    }
+}
+
+// Undecoded contents of a `<Keybox>` element
+#[derive(Default)]
+pub(crate) struct PendingKeybox {
+    pub(crate) device_id: Option<String>,
+    pub(crate) keys: Vec<PendingKey>,
+    // `algorithm` attribute and undecoded body of a `<Signature>` element
+    pub(crate) signature: Option<(String, String)>,
+    // Declared algorithms of the keys skipped because none was recognized
+    pub(crate) skipped_algorithms: Vec<String>,
+    // `version` attribute of the enclosing AndroidAttestation element
+    pub(crate) format_version: Option<String>,
+}
+
+impl PendingKeybox {
+    // Names each key in errors. The first key of each algorithm keeps the short
+    // context, e.g. `keybox 1 EC`; later ones are numbered, e.g. `keybox 1 EC key 2`.
+    pub(crate) fn key_contexts(&self, index: usize) -> Vec<String> {
+        self.keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let nth = self.keys[..=i].iter().filter(|k| k.algorithm == key.algorithm).count();
+                let mut context = format!("keybox {} {}", index, key.algorithm.prefix());
+                if nth > 1 {
+                    context.push_str(&format!(" key {}", nth));
+                }
+                context
+            })
+            .collect()
+    }
+
+    // See `PendingKey::decode` for `options` and `on_problem`
+    fn decode(
+        self,
+        index: usize,
+        options: &ParseOptions,
+        on_problem: &mut dyn FnMut(KeyboxError) -> Result<(), KeyboxError>,
+    ) -> Result<Keybox, KeyboxError> {
+        let contexts = self.key_contexts(index);
+        let keys = self
+            .keys
+            .into_iter()
+            .zip(&contexts)
+            .map(|(key, context)| key.decode(context, options, on_problem))
+            .collect::<Result<Vec<KeyEntry>, KeyboxError>>()?;
+        // A signature that cannot be decoded is an anomaly and is left out
+        let signature = match self.signature {
+            Some((algorithm, body)) => {
+                match decode_base64(&clean_body(&body).0, format!("keybox {} signature", index)) {
+                    Ok(value) => Some(KeyboxSignature { algorithm, value }),
+                    Err(e) => on_problem(e).map(|_| None)?,
+                }
+            }
+            None => None,
+        };
+
+        Ok(Keybox {
+            device_id: self.device_id,
+            format_version: self.format_version,
+            keys,
+            signature,
+        })
+    }
+}
+
+/// Parses the keybox XML file at `path`.
+///
+/// This is a convenience wrapper around [`parse_keybox_reader`].
+pub fn parse_keybox(path: &Path) -> Result<Vec<Keybox>, KeyboxError> {
+    parse_keybox_reader(BufReader::new(File::open(path)?))
+}
+
+/// Directory under `ANDROID_BUILD_TOP` searched for a keybox when `KEYBOX_PATH`
+/// is not set; see [`default_keybox_path`].
+pub const DEFAULT_KEYBOX_DIR: &str = "vendor/keybox";
+
+/// Resolves the keybox file the way the build script does, for finding the
+/// same keybox at runtime.
+///
+/// `KEYBOX_PATH` may name the keybox itself, or a directory holding
+/// `KEYBOX_FILE` (`keybox.xml` by default), and is returned even if nothing is
+/// there. Without it, `KEYBOX_FILE` in [`DEFAULT_KEYBOX_DIR`] under
+/// `ANDROID_BUILD_TOP`, e.g. `$ANDROID_BUILD_TOP/vendor/keybox/keybox.xml`, is
+/// returned if that file exists. Returns `None` if neither gives a path.
+///
+/// A `KEYBOX_PATH` directory without `KEYBOX_FILE` in it is read by the build
+/// script as a keybox per device; see [`device_keybox_paths`].
+pub fn default_keybox_path() -> Option<PathBuf> {
+    let file_name = std::env::var("KEYBOX_FILE").unwrap_or_else(|_| "keybox.xml".to_string());
+    if let Some(path) = std::env::var_os("KEYBOX_PATH").map(PathBuf::from) {
+        return Some(if path.is_file() { path } else { path.join(file_name) });
+    }
+    let build_top = std::env::var_os("ANDROID_BUILD_TOP")?;
+    Some(Path::new(&build_top).join(DEFAULT_KEYBOX_DIR).join(file_name)).filter(|path| path.is_file())
+}
+
+/// Lists the per-device keyboxes in `dir`, a directory with one subdirectory
+/// per device holding its keybox, e.g. `pixel-6/keybox.xml`, as the build
+/// script reads it when `KEYBOX_PATH` names such a directory.
+///
+/// Each subdirectory is paired with the path of its `KEYBOX_FILE`
+/// (`keybox.xml` by default), whether or not that exists, sorted by name.
+/// Hidden subdirectories such as `.git` and names that are not UTF-8 are
+/// skipped.
+pub fn device_keybox_paths(dir: &Path) -> Result<Vec<(String, PathBuf)>, KeyboxError> {
+    let file_name = std::env::var("KEYBOX_FILE").unwrap_or_else(|_| "keybox.xml".to_string());
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !name.starts_with('.') && entry.path().is_dir() {
+            devices.push((name, entry.path().join(&file_name)));
+        }
+    }
+    devices.sort();
+    Ok(devices)
+}
+
+/// Parses the keybox found by [`default_keybox_path`].
+///
+/// Fails with [`KeyboxError::MissingKeyboxPath`] if no keybox path is found,
+/// and like [`parse_keybox`] otherwise.
+pub fn parse_default_keybox() -> Result<Vec<Keybox>, KeyboxError> {
+    parse_keybox(&default_keybox_path().ok_or(KeyboxError::MissingKeyboxPath)?)
+}
+
+/// Parses keybox XML already held in memory, e.g. downloaded or decrypted.
+///
+/// This is [`parse_keybox_reader`] over `data`, so a BOM and, with the `gzip`
+/// feature, gzip compression are handled the same way.
+pub fn parse_keybox_bytes(data: &[u8]) -> Result<Vec<Keybox>, KeyboxError> {
+    parse_keybox_reader(data)
+}
+
+// Case-insensitive match of a Key `algorithm` attribute or Algorithm element, accepting
+// `ec` for ECDSA
+fn match_algorithm(value: &str) -> Option<KeyAlgorithm> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("ecdsa") || value.eq_ignore_ascii_case("ec") {
+        Some(KeyAlgorithm::Ecdsa)
+    } else if value.eq_ignore_ascii_case("rsa") {
+        Some(KeyAlgorithm::Rsa)
+    } else {
+        None
+    }
+}
+
+// Describes where in the keybox the reader is, e.g. `keybox 1 EC certificate 2`
+fn element_context(keybox: usize, key: Option<KeyAlgorithm>, certificate: Option<usize>, private_key: bool) -> String {
+    let Some(algorithm) = key else {
+        return format!("keybox {}", keybox);
+    };
+    match certificate {
+        Some(n) => format!("keybox {} {} certificate {}", keybox, algorithm.prefix(), n),
+        None if private_key => format!("keybox {} {} private key", keybox, algorithm.prefix()),
+        None => format!("keybox {} {} key", keybox, algorithm.prefix()),
+    }
+}
+
+// Checks a declared format version, returning whether it is a later minor version of
+// `FORMAT_VERSION` that is parsed anyway. Other major versions and versions that are
+// not numbers are errors.
+fn check_format_version(version: &str) -> Result<bool, KeyboxError> {
+    let mut parts = version.trim().splitn(2, '.');
+    let major = parts.next().and_then(|major| major.parse::<u32>().ok());
+    let minor = match parts.next() {
+        Some(minor) => minor.parse::<u32>().ok(),
+        None => Some(0),
+    };
+    match (major, minor) {
+        (Some(FORMAT_VERSION), Some(minor)) => Ok(minor > 0),
+        _ => Err(KeyboxError::MalformedKeybox(format!(
+            "unsupported keybox format version {:?}",
+            version
+        ))),
+    }
+}
+
+// Parses the text of a count element such as `NumberOfKeyboxes`
+fn parse_count(element: &str, text: &str) -> Result<usize, KeyboxError> {
+    text.trim()
+        .parse()
+        .map_err(|_| KeyboxError::MalformedKeybox(format!("invalid {} {:?}", element, text)))
+}
+
+// Compares a declared count against what was parsed. Absent counts are not checked.
+fn check_count(element: &str, declared: Option<usize>, found: usize, context: &str) -> Result<(), KeyboxError> {
+    match declared {
+        Some(declared) if declared != found => Err(KeyboxError::MalformedKeybox(format!(
+            "{}{} is {} but {} were found",
+            context, element, declared, found
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Parses keybox XML from `reader`, returning one [`Keybox`] per `<Keybox>`
+/// element with every ECDSA and RSA key decoded, each with its own chain and
+/// private key.
+///
+/// The keybox may be embedded in a larger document, e.g. a device provisioning
+/// profile: only `<Keybox>` elements directly inside `<AndroidAttestation>`, or
+/// at the root, and `<Key>` elements directly inside them are read, and
+/// same-named elements elsewhere are ignored.
+///
+/// A key's algorithm is read from its `algorithm` attribute or, without a
+/// recognized one, from an `<Algorithm>` child element. Either form is matched
+/// case-insensitively, with `ec` accepted for ECDSA; the declared value is kept
+/// in the model.
+///
+/// Declared counts (`NumberOfKeyboxes`, `NumberOfKeys` and each chain's
+/// `NumberOfCertificates`) must match what was parsed; absent counts are not
+/// checked. So must the major version of a `version` attribute on
+/// `<AndroidAttestation>`, which is kept as [`Keybox::format_version`]. With
+/// the `gzip` feature, gzip-compressed input is detected and decompressed
+/// automatically.
+pub fn parse_keybox_reader<R: Read>(reader: R) -> Result<Vec<Keybox>, KeyboxError> {
+    read_keyboxes(reader, &mut |_, e| Err(e))?
+        .into_iter()
+        .enumerate()
+        .map(|(i, pending)| pending.decode(i + 1, &ParseOptions::default(), &mut Err))
+        .collect()
+}
+
+/// How strictly [`parse_keybox_with`] treats anomalies in an otherwise readable
+/// keybox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Fail on the first anomaly instead of collecting it as a warning.
+    pub strict: bool,
+    /// Require every key to have a certificate chain ending in a self-issued
+    /// root. Without the `x509` feature only a non-empty chain is required.
+    pub require_full_chain: bool,
+    /// With the `verify` feature, compare each ECDSA private key with its leaf
+    /// certificate.
+    pub verify_key_cert_match: bool,
+    /// Keep each certificate's base64 exactly as it appeared in the XML, in
+    /// [`KeyEntry::raw_certificates`].
+    pub keep_raw_base64: bool,
+}
+
+// Lenient, with the key match checked but no root required and no raw base64 kept
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strict: false,
+            require_full_chain: false,
+            verify_key_cert_match: true,
+            keep_raw_base64: false,
+        }
+    }
+}
+
+/// Keyboxes parsed by [`parse_keybox_with`] together with the anomalies that
+/// were tolerated while parsing them.
+#[derive(Debug, Default)]
+pub struct ParsedKeyboxes {
+    pub keyboxes: Vec<Keybox>,
+    /// Each anomaly as the error strict parsing would have returned.
+    pub warnings: Vec<KeyboxError>,
+}
+
+// Reports problems of a decoded key that parsing otherwise accepts
+fn check_key(
+    key: &KeyEntry,
+    context: &str,
+    options: &ParseOptions,
+    on_problem: &mut dyn FnMut(KeyboxError) -> Result<(), KeyboxError>,
+) -> Result<(), KeyboxError> {
+    let mut problems = Vec::new();
+    if key.private_key.is_none() {
+        problems.push("private key is missing");
+    }
+    if key.certificates.is_empty() {
+        problems.push("certificate chain is missing");
+    }
+    if key.padding_repaired {
+        problems.push("base64 padding was repaired");
+    }
+    if key.url_safe_base64 {
+        problems.push("base64 uses the URL-safe alphabet");
+    }
+    #[cfg(feature = "x509")]
+    if let (true, Some(root)) = (options.require_full_chain, key.root_certificate()) {
+        if !crate::certificate::is_self_issued(root)? {
+            problems.push("certificate chain does not end in a root certificate");
+        }
+    }
+    #[cfg(not(feature = "x509"))]
+    let _ = options;
+    for problem in problems {
+        on_problem(KeyboxError::MalformedKeybox(format!("{} {}", context, problem)))?;
+    }
+    Ok(())
+}
+
+/// Parses keybox XML from `reader` like [`parse_keybox_reader`], but treats
+/// anomalies as `options` asks.
+///
+/// Anomalies are mismatched or non-numeric declared counts, signatures that
+/// are not base64, unsupported format versions, failed `verify` checks, keys
+/// skipped for an unrecognized algorithm, missing private keys or chains,
+/// repaired base64 padding, repeated `DeviceID`s and, if required, chains
+/// without a root. In strict mode the first one is returned as an error;
+/// otherwise they are collected into [`ParsedKeyboxes::warnings`]. A later minor
+/// format version is a warning even in strict mode. Input that cannot be
+/// decoded at all is always an error.
+pub fn parse_keybox_with<R: Read>(reader: R, options: &ParseOptions) -> Result<ParsedKeyboxes, KeyboxError> {
+    let mut warnings = Vec::new();
+    let mut on_problem = |e: KeyboxError| {
+        if options.strict {
+            return Err(e);
+        }
+        warnings.push(e);
+        Ok(())
+    };
+
+    let pending = read_keyboxes(reader, &mut |_, e| on_problem(e))?;
+    if pending.is_empty() {
+        on_problem(KeyboxError::MalformedKeybox("no keyboxes found".to_string()))?;
+    }
+    let repeats = repeated_device_ids(pending.iter().map(|keybox| keybox.device_id.as_deref()));
+
+    let mut keyboxes = Vec::new();
+    for (i, pending) in pending.into_iter().enumerate() {
+        let index = i + 1;
+        for algorithm in &pending.skipped_algorithms {
+            let message = format!("keybox {}: skipped key with unrecognized algorithm {:?}", index, algorithm);
+            on_problem(KeyboxError::MalformedKeybox(message))?;
+        }
+        let contexts = pending.key_contexts(index);
+        let keybox = pending.decode(index, options, &mut on_problem)?;
+        if keybox.keys.is_empty() {
+            on_problem(KeyboxError::MalformedKeybox(format!("keybox {}: no keys found", index)))?;
+        }
+        for (key, context) in keybox.keys.iter().zip(&contexts) {
+            check_key(key, context, options, &mut on_problem)?;
+        }
+        keyboxes.push(keybox);
+    }
+    for (first, repeat) in repeats {
+        let message = format!("keybox {}: same DeviceID as keybox {}", repeat, first);
+        on_problem(KeyboxError::MalformedKeybox(message))?;
+    }
+    if let Some(version) = keyboxes.iter().find_map(|keybox| keybox.format_version.as_deref()) {
+        if let Ok(true) = check_format_version(version) {
+            warnings.push(KeyboxError::MalformedKeybox(format!(
+                "format version {} is newer than {}.0 and may not be fully read",
+                version, FORMAT_VERSION
+            )));
+        }
+    }
+
+    Ok(ParsedKeyboxes { keyboxes, warnings })
+}
+
+// Pairs each repeated DeviceID with the first keybox that used it, as 1-based
+// `(first, repeat)` indices. Keyboxes without a DeviceID are skipped.
+pub(crate) fn repeated_device_ids<'a>(device_ids: impl Iterator<Item = Option<&'a str>>) -> Vec<(usize, usize)> {
+    // A map rather than a list, since aggregated bundles hold thousands of keyboxes
+    let mut first_seen: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut repeats = Vec::new();
+    for (i, device_id) in device_ids.enumerate() {
+        let Some(device_id) = device_id else {
+            continue;
+        };
+        let first = *first_seen.entry(device_id).or_insert(i + 1);
+        if first != i + 1 {
+            repeats.push((first, i + 1));
+        }
+    }
+    repeats
+}
+
+/// Finds keyboxes whose `DeviceID` repeats that of an earlier keybox, which
+/// makes selecting a keybox by device id ambiguous.
+///
+/// Each repeat is returned as 1-based `(first, repeat)` keybox indices, e.g.
+/// `(1, 3)` when the third keybox has the same `DeviceID` as the first.
+pub fn duplicate_device_ids(keyboxes: &[Keybox]) -> Vec<(usize, usize)> {
+    repeated_device_ids(keyboxes.iter().map(|keybox| keybox.device_id.as_deref()))
+}
+
+/// Parses keybox XML from `reader` like [`parse_keybox_reader`], but passes
+/// each key to `on_key` together with its keybox's `DeviceID` instead of
+/// returning them all.
+///
+/// Only the keybox being read is held in memory, so large aggregated bundles
+/// can be searched for one device without decoding every keybox into a
+/// vector. `on_key` may already have been called when a later error is
+/// returned, e.g. for a `NumberOfKeyboxes` that does not match.
+pub fn for_each_key<R, F>(reader: R, mut on_key: F) -> Result<(), KeyboxError>
+where
+    R: Read,
+    F: FnMut(Option<&str>, KeyEntry),
+{
+    walk_keyboxes(reader, &mut |_, e| Err(e), &mut |index, pending| {
+        let keybox = pending.decode(index, &ParseOptions::default(), &mut Err)?;
+        for key in keybox.keys {
+            on_key(keybox.device_id.as_deref(), key);
+        }
+        Ok(())
+    })
+}
+
+// Walks the keybox XML and collects each keybox's undecoded contents; see
+// `walk_keyboxes`
+pub(crate) fn read_keyboxes<R: Read>(
+    reader: R,
+    on_problem: &mut dyn FnMut(&'static str, KeyboxError) -> Result<(), KeyboxError>,
+) -> Result<Vec<PendingKeybox>, KeyboxError> {
+    let mut keyboxes = Vec::new();
+    walk_keyboxes(reader, on_problem, &mut |_, pending| {
+        keyboxes.push(pending);
+        Ok(())
+    })?;
+    Ok(keyboxes)
+}
+
+// Walks the keybox XML, passing each keybox's undecoded contents and 1-based index to
+// `on_keybox` as soon as its element ends. Declared counts that do not match and
+// unsupported format versions are passed to `on_problem` along with what they concern,
+// `declared counts` or `format version`. It either fails the walk by returning the
+// error or records it and lets the walk continue.
+fn walk_keyboxes<R: Read>(
+    reader: R,
+    on_problem: &mut dyn FnMut(&'static str, KeyboxError) -> Result<(), KeyboxError>,
+    on_keybox: &mut dyn FnMut(usize, PendingKeybox) -> Result<(), KeyboxError>,
+) -> Result<(), KeyboxError> {
+    let parser = EventReader::new(open_input(reader)?);
+
+    let mut inside_certificate = false;
+    let mut inside_private_key = false;
+    let mut inside_signature = false;
+    let mut inside_algorithm = false;
+    // Contents of the Key being read, if its algorithm is recognized or may still be
+    // given by an Algorithm element. They are only added to `current` when the Key
+    // ends, so its children may come in any order.
+    let mut current_key: Option<PendingKey> = None;
+    // Set while the current Key has no recognized algorithm attribute. Its pending key
+    // holds a placeholder algorithm until an Algorithm element names one, and is
+    // dropped at the end of the Key if none does.
+    let mut algorithm_unknown = false;
+    // Name and text of the count element currently being read, e.g. `NumberOfKeys`
+    let mut count_element: Option<(String, String)> = None;
+    let mut declared_keyboxes: Option<usize> = None;
+    let mut declared_keys: Option<usize> = None;
+    let mut declared_certs: Option<usize> = None;
+    let mut key_count = 0;
+    let mut chain_cert_count = 0;
+    // Text of the current Certificate or PrivateKey element. The reader may split a
+    // long text node into several events, so it is only used once the element ends.
+    let mut text_buffer = String::new();
+    let mut current: Option<PendingKeybox> = None;
+    // Number of keyboxes passed to `on_keybox` so far
+    let mut keybox_count = 0;
+    let mut format_version: Option<String> = None;
+    // Nesting depth of the element being read, and of the open AndroidAttestation,
+    // Keybox and Key elements. Keyboxes are only read as children of
+    // AndroidAttestation, or as the root, and keys as children of a keybox, so that
+    // elements of the same names elsewhere in a larger document, e.g. a device
+    // provisioning profile, are ignored.
+    let mut depth = 0;
+    let mut attestation_depth: Option<usize> = None;
+    let mut keybox_depth: Option<usize> = None;
+    let mut key_depth: Option<usize> = None;
+
+    for event in parser {
+        let event = event.map_err(|e| {
+            let key = current_key.as_ref().filter(|_| !algorithm_unknown);
+            let context = current.as_ref().map(|_| {
+                let certificate = inside_certificate.then_some(chain_cert_count);
+                element_context(keybox_count + 1, key.map(|key| key.algorithm), certificate, inside_private_key)
+            });
+            KeyboxError::xml(e, context)
+        })?;
+        match event {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                depth += 1;
+                let in_attestation = attestation_depth == Some(depth - 1);
+                let in_keybox = keybox_depth == Some(depth - 1);
+                let in_key = key_depth.is_some();
+                match name.local_name.as_str() {
+                    "NumberOfKeyboxes" if in_attestation => {
+                        count_element = Some((name.local_name.clone(), String::new()));
+                    }
+                    "NumberOfKeys" if in_keybox => {
+                        count_element = Some((name.local_name.clone(), String::new()));
+                    }
+                    "NumberOfCertificates" if in_key => {
+                        count_element = Some((name.local_name.clone(), String::new()));
+                    }
+                    "AndroidAttestation" if attestation_depth.is_none() => {
+                        attestation_depth = Some(depth);
+                        format_version = attributes
+                            .into_iter()
+                            .find(|attr| attr.name.local_name == "version")
+                            .map(|attr| attr.value);
+                        if let Some(version) = &format_version {
+                            check_format_version(version).map(|_| ()).or_else(|e| on_problem("format version", e))?;
+                        }
+                    }
+                    "Keybox" if in_attestation || depth == 1 => {
+                        keybox_depth = Some(depth);
+                        current = Some(PendingKeybox {
+                            device_id: attributes
+                                .into_iter()
+                                .find(|attr| attr.name.local_name == "DeviceID")
+                                .map(|attr| attr.value),
+                            format_version: format_version.clone(),
+                            ..PendingKeybox::default()
+                        });
+                        declared_keys = None;
+                        key_count = 0;
+                    }
+                    "Key" if in_keybox => {
+                        key_count += 1;
+                        key_depth = Some(depth);
+                        // Attributes are keyed by local name, so `kb:algorithm` is read as
+                        // `algorithm`; an unprefixed attribute wins over a prefixed one
+                        let mut by_name = BTreeMap::new();
+                        for attr in attributes {
+                            if attr.name.prefix.is_none() || !by_name.contains_key(&attr.name.local_name) {
+                                by_name.insert(attr.name.local_name, attr.value);
+                            }
+                        }
+                        let mut attributes: BTreeMap<String, String> = by_name;
+                        let declared = attributes.remove("algorithm").unwrap_or_default();
+                        // Without a recognized attribute, an Algorithm element may still
+                        // name the algorithm
+                        let algorithm = match_algorithm(&declared);
+                        algorithm_unknown = algorithm.is_none();
+                        current_key = Some(PendingKey {
+                            algorithm: algorithm.unwrap_or(KeyAlgorithm::Ecdsa),
+                            declared_algorithm: declared,
+                            certs: Vec::new(),
+                            private_key: None,
+                            extra_private_keys: Vec::new(),
+                            private_key_format: None,
+                            encoding: None,
+                            attributes,
+                        });
+                    }
+                    "CertificateChain" if in_key => {
+                        declared_certs = None;
+                        chain_cert_count = 0;
+                    }
+                    "Certificate" if in_key => chain_cert_count += 1,
+                    "Algorithm" if in_key => inside_algorithm = true,
+                    // A keybox-level integrity block; Key elements have none
+                    "Signature" if in_keybox => {
+                        if let Some(pending) = current.as_mut() {
+                            let algorithm = attributes
+                                .into_iter()
+                                .find(|attr| attr.name.local_name == "algorithm")
+                                .map(|attr| attr.value);
+                            pending.signature = Some((algorithm.unwrap_or_default(), String::new()));
+                            inside_signature = true;
+                        }
+                    }
+                    _ => {}
+                }
+                if name.local_name == "Certificate" && current_key.is_some() {
+                    inside_certificate = true;
+                }
+                if name.local_name == "PrivateKey" && current_key.is_some() {
+                    inside_private_key = true;
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                let closing = depth;
+                let closes = |open: Option<usize>| open == Some(closing);
+                depth -= 1;
+                if let Some((element, text)) = count_element.take() {
+                    // A count that is not a number is an anomaly and is not checked
+                    let count = parse_count(&element, &text)
+                        .map(Some)
+                        .or_else(|e| on_problem("declared counts", e).map(|_| None))?;
+                    match element.as_str() {
+                        "NumberOfKeyboxes" => declared_keyboxes = count,
+                        "NumberOfKeys" => declared_keys = count,
+                        _ => declared_certs = count,
+                    }
+                }
+                if name.local_name == "AndroidAttestation" && closes(attestation_depth) {
+                    attestation_depth = None;
+                }
+                let context = format!("keybox {}: ", keybox_count + 1);
+                if name.local_name == "Keybox" && closes(keybox_depth) {
+                    keybox_depth = None;
+                    check_count("NumberOfKeys", declared_keys, key_count, &context)
+                        .or_else(|e| on_problem("declared counts", e))?;
+                    if let Some(pending) = current.take() {
+                        keybox_count += 1;
+                        on_keybox(keybox_count, pending)?;
+                    }
+                }
+                if name.local_name == "CertificateChain" && key_depth.is_some() {
+                    check_count("NumberOfCertificates", declared_certs, chain_cert_count, &context)
+                        .or_else(|e| on_problem("declared counts", e))?;
+                }
+                if name.local_name == "Algorithm" && inside_algorithm {
+                    // The attribute takes precedence when both name a recognized algorithm
+                    let key = current_key.as_mut().filter(|_| algorithm_unknown);
+                    if let (Some(key), Some(algorithm)) = (key, match_algorithm(&text_buffer)) {
+                        key.algorithm = algorithm;
+                        key.declared_algorithm = text_buffer.trim().to_string();
+                        algorithm_unknown = false;
+                    }
+                    text_buffer.clear();
+                    inside_algorithm = false;
+                }
+                if name.local_name == "Key" && closes(key_depth) {
+                    key_depth = None;
+                    if let (Some(pending), Some(key)) = (current.as_mut(), current_key.take()) {
+                        if algorithm_unknown {
+                            pending.skipped_algorithms.push(key.declared_algorithm);
+                        } else {
+                            pending.keys.push(key);
+                        }
+                    }
+                    algorithm_unknown = false;
+                }
+                if name.local_name == "Certificate" && inside_certificate {
+                    // Empty elements, e.g. placeholders left by a template, and empty PEM
+                    // blocks are dropped, so they neither take a slot in the chain nor
+                    // count towards NumberOfCertificates
+                    let (mut certs, encoding) = clean_bodies(&text_buffer);
+                    certs.retain(|cert| !cert.is_empty());
+                    // The element was counted when it started; each PEM block in it is a
+                    // certificate of its own
+                    chain_cert_count = (chain_cert_count + certs.len()).saturating_sub(1);
+                    if let Some(key) = current_key.as_mut().filter(|_| !certs.is_empty()) {
+                        key.certs.extend(certs);
+                        key.encoding = Some(BodyEncoding::merge(key.encoding, encoding));
+                    }
+                    text_buffer.clear();
+                    inside_certificate = false;
+                }
+                if name.local_name == "Signature" && inside_signature {
+                    if let Some((_, body)) = current.as_mut().and_then(|pending| pending.signature.as_mut()) {
+                        *body = std::mem::take(&mut text_buffer);
+                    }
+                    inside_signature = false;
+                }
+                if name.local_name == "PrivateKey" && inside_private_key {
+                    if let Some(key) = current_key.as_mut().filter(|_| !text_buffer.is_empty()) {
+                        let (private_key, encoding) = clean_body(&text_buffer);
+                        key.encoding = Some(BodyEncoding::merge(key.encoding, encoding));
+                        // The first key is the one used; later ones, e.g. backups, are kept
+                        // alongside it
+                        if key.private_key.is_some() {
+                            key.extra_private_keys.push(private_key);
+                        } else {
+                            key.private_key = Some(private_key);
+                            key.private_key_format =
+                                pem_label(&text_buffer).map(|label| PrivateKeyFormat::from_label(&label));
+                        }
+                    }
+                    text_buffer.clear();
+                    inside_private_key = false;
+                }
+            }
+            // Whitespace next to a CDATA section is reported as Characters, so it is
+            // skipped rather than treated as certificate or key content
+            XmlEvent::Characters(text) | XmlEvent::CData(text) if !text.trim().is_empty() => {
+                if let Some((_, count_text)) = count_element.as_mut() {
+                    count_text.push_str(&text);
+                } else if inside_certificate || inside_private_key || inside_signature || inside_algorithm {
+                    text_buffer.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    check_count("NumberOfKeyboxes", declared_keyboxes, keybox_count, "").or_else(|e| on_problem("declared counts", e))
+}
+
+/// Counts the `Keybox`, `Key`, `Certificate` and `PrivateKey` elements in keybox
+/// XML from `reader` without decoding anything, for a cheap sanity check before
+/// a full parse.
+///
+/// Only the elements a parse would read are counted: keyboxes in the
+/// `AndroidAttestation` element or at the root, keys in a keybox, and
+/// certificates and private keys in a key.
+pub fn keybox_stats<R: Read>(reader: R) -> Result<KeyboxStats, KeyboxError> {
+    let mut stats = KeyboxStats::default();
+    // Nesting depth as in `walk_keyboxes`
+    let mut depth = 0;
+    let mut attestation_depth: Option<usize> = None;
+    let mut keybox_depth: Option<usize> = None;
+    let mut key_depth: Option<usize> = None;
+    for event in EventReader::new(open_input(reader)?) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                depth += 1;
+                match name.local_name.as_str() {
+                    "AndroidAttestation" if attestation_depth.is_none() => attestation_depth = Some(depth),
+                    "Keybox" if attestation_depth == Some(depth - 1) || depth == 1 => {
+                        keybox_depth = Some(depth);
+                        stats.keyboxes += 1;
+                    }
+                    "Key" if keybox_depth == Some(depth - 1) => {
+                        key_depth = Some(depth);
+                        stats.keys += 1;
+                    }
+                    "Certificate" if key_depth.is_some() => stats.certificates += 1,
+                    "PrivateKey" if key_depth.is_some() => stats.private_keys += 1,
+                    _ => {}
+                }
+            }
+            XmlEvent::EndElement { .. } => {
+                for open in [&mut attestation_depth, &mut keybox_depth, &mut key_depth] {
+                    if *open == Some(depth) {
+                        *open = None;
+                    }
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(stats)
+}
@@ -0,0 +1,178 @@
+// src/pem.rs
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use base64::Engine;
+
+use crate::error::KeyboxError;
+use crate::model::BodyEncoding;
+
+// Extracts the base64 bodies of a certificate or private key element. Text with a
+// BEGIN marker is PEM and yields one body per block, so a whole chain written as
+// back-to-back blocks in one element is split; text outside the blocks is ignored.
+// Anything else is a single headerless base64 DER body. Each line is trimmed first,
+// so indentation and CRLF line endings are tolerated. The text is scanned once,
+// with every body gathered in one reused buffer.
+#[cfg_attr(keybox_no_std, allow(dead_code))]
+pub(crate) fn clean_bodies(text: &str) -> (Vec<String>, BodyEncoding) {
+    let mut bodies = Vec::new();
+    let mut buffer = String::with_capacity(text.len());
+    // Whether a BEGIN marker has been seen, and whether a block is open
+    let mut pem = false;
+    let mut in_block = false;
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if line.starts_with("-----BEGIN") {
+            // Anything gathered before the first block was not a body after all
+            flush(&mut buffer, &mut bodies, in_block);
+            pem = true;
+            in_block = true;
+        } else if pem && line.starts_with("-----END") {
+            flush(&mut buffer, &mut bodies, in_block);
+            in_block = false;
+        } else if in_block || !pem {
+            buffer.push_str(line);
+        }
+    }
+    if !pem {
+        return (vec![buffer], BodyEncoding::Base64);
+    }
+    flush(&mut buffer, &mut bodies, in_block);
+    (bodies, BodyEncoding::Pem)
+}
+
+// Moves the body gathered in `buffer` into `bodies` if it belongs to a block, and
+// empties the buffer for the next one either way
+#[cfg_attr(keybox_no_std, allow(dead_code))]
+fn flush(buffer: &mut String, bodies: &mut Vec<String>, keep: bool) {
+    if keep {
+        bodies.push(buffer.as_str().into());
+    }
+    buffer.clear();
+}
+
+// Like `clean_bodies`, but keeps only the first body, e.g. of a private key
+#[cfg_attr(keybox_no_std, allow(dead_code))]
+pub(crate) fn clean_body(text: &str) -> (String, BodyEncoding) {
+    let (bodies, encoding) = clean_bodies(text);
+    (bodies.into_iter().next().unwrap_or_default(), encoding)
+}
+
+// Returns the label of the first `-----BEGIN <label>-----` line, if any
+#[cfg_attr(keybox_no_std, allow(dead_code))]
+pub(crate) fn pem_label(pem: &str) -> Option<String> {
+    pem.lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix("-----BEGIN ")?.strip_suffix("-----"))
+        .map(str::to_string)
+}
+
+// What `decode_base64_repairing` had to work around to decode a body
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(keybox_no_std, allow(dead_code))]
+pub(crate) struct Repairs {
+    // Missing or wrong padding was replaced
+    pub(crate) padding: bool,
+    // The body used the URL-safe alphabet, with `-` and `_` for `+` and `/`
+    pub(crate) url_safe: bool,
+}
+
+// Decodes base64 after dropping all whitespace, including stray spaces or tabs inside
+// the body. Padding that was stripped or mangled, e.g. by hand-editing, is repaired
+// by replacing any trailing `=` with the correct number. A body that is not standard
+// base64 but contains `-` or `_`, as written by some web tools, is retried with the
+// URL-safe alphabet. The returned repairs say which of these applied.
+pub(crate) fn decode_base64_repairing(data: &str, context: String) -> Result<(Vec<u8>, Repairs), KeyboxError> {
+    // Bodies from `clean_bodies` have no whitespace left, so they are only copied
+    // when padding has to be repaired
+    let stripped: String;
+    let data = if data.contains(char::is_whitespace) {
+        stripped = data.chars().filter(|c| !c.is_whitespace()).collect();
+        stripped.as_str()
+    } else {
+        data
+    };
+    let unpadded = data.trim_end_matches('=');
+    let padding = (4 - unpadded.len() % 4) % 4;
+    let repaired = unpadded.len() + padding != data.len();
+    let padded: String;
+    let input = if repaired {
+        padded = format!("{}{}", unpadded, "=".repeat(padding));
+        padded.as_str()
+    } else {
+        data
+    };
+    let mut repairs = Repairs {
+        padding: repaired,
+        url_safe: false,
+    };
+    let der = match STANDARD.decode(input) {
+        Ok(der) => der,
+        // The error reported is that of the standard alphabet, which most keyboxes use
+        Err(source) => match URL_SAFE.decode(input).ok().filter(|_| input.contains(['-', '_'])) {
+            Some(der) => {
+                repairs.url_safe = true;
+                der
+            }
+            None => {
+                return Err(KeyboxError::Base64Decode {
+                    context,
+                    length: data.len(),
+                    source,
+                })
+            }
+        },
+    };
+    Ok((der, repairs))
+}
+
+// Like `decode_base64_repairing`, for callers that do not report repairs
+pub(crate) fn decode_base64(data: &str, context: String) -> Result<Vec<u8>, KeyboxError> {
+    decode_base64_repairing(data, context).map(|(der, _)| der)
+}
+
+/// Converts PEM text to DER, returning the first block if there are several.
+///
+/// Any `-----BEGIN ...-----`/`-----END ...-----` label is accepted, text
+/// outside the blocks is ignored, and text without markers is decoded as plain
+/// base64, the same way keybox bodies are read. Missing or wrong `=` padding is
+/// repaired before decoding.
+pub fn pem_to_der(pem: &str) -> Result<Vec<u8>, KeyboxError> {
+    let body = clean_bodies(pem)
+        .0
+        .into_iter()
+        .find(|body| !body.is_empty())
+        .ok_or_else(|| KeyboxError::MalformedKeybox("no PEM data found".to_string()))?;
+    decode_base64(&body, "PEM block 1".to_string())
+}
+
+/// Converts every block of PEM text to DER, in order, skipping empty blocks.
+pub fn pem_to_der_all(pem: &str) -> Result<Vec<Vec<u8>>, KeyboxError> {
+    clean_bodies(pem)
+        .0
+        .iter()
+        .filter(|body| !body.is_empty())
+        .enumerate()
+        .map(|(i, body)| decode_base64(body, format!("PEM block {}", i + 1)))
+        .collect()
+}
+
+/// Encodes DER bytes as base64 with the given `engine`, for example
+/// `base64::engine::general_purpose::URL_SAFE_NO_PAD`.
+pub fn der_to_base64<E: Engine>(der: &[u8], engine: &E) -> String {
+    engine.encode(der)
+}
+
+/// Encodes DER bytes as PEM with the given label, such as `CERTIFICATE`,
+/// wrapping the standard base64 body at 64 characters per line.
+pub fn der_to_pem(der: &[u8], label: &str) -> String {
+    let body = der_to_base64(der, &STANDARD);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in body.as_bytes().chunks(64) {
+        pem.push_str(core::str::from_utf8(line).unwrap()); // base64 is ASCII
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
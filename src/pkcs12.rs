@@ -0,0 +1,112 @@
+// src/pkcs12.rs
+use ring::hmac;
+use sha2::{Digest, Sha256};
+
+use crate::error::KeyboxError;
+use crate::key::{detect_format, encode_tlv, to_pkcs8};
+use crate::model::{Keybox, PrivateKeyFormat};
+
+// DER encodings of the OIDs used in the bundle
+const ID_DATA: &[u8] = &[0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const KEY_BAG: &[u8] = &[0x06, 0x0b, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x01];
+const CERT_BAG: &[u8] = &[0x06, 0x0b, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x03];
+const X509_CERTIFICATE: &[u8] = &[0x06, 0x0a, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x16, 0x01];
+const LOCAL_KEY_ID: &[u8] = &[0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x15];
+const SHA256: &[u8] = &[0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const DER_NULL: &[u8] = &[0x05, 0x00];
+
+// Iterations of the MAC key derivation, OpenSSL's default
+const MAC_ITERATIONS: u32 = 2048;
+// Block size of SHA-256, the `v` of RFC 7292 appendix B
+const BLOCK: usize = 64;
+
+fn sequence(parts: &[&[u8]]) -> Vec<u8> {
+    encode_tlv(0x30, &parts.concat())
+}
+
+fn explicit(content: &[u8]) -> Vec<u8> {
+    encode_tlv(0xa0, content)
+}
+
+fn integer(value: u32) -> Vec<u8> {
+    let mut bytes: Vec<u8> = value.to_be_bytes().into_iter().skip_while(|&b| b == 0).collect();
+    if bytes.first().is_none_or(|&b| b & 0x80 != 0) {
+        bytes.insert(0, 0);
+    }
+    encode_tlv(0x02, &bytes)
+}
+
+// A bag attribute set holding the localKeyId tying the leaf to the key
+fn local_key_id(id: &[u8]) -> Vec<u8> {
+    let attribute = sequence(&[LOCAL_KEY_ID, &encode_tlv(0x31, &encode_tlv(0x04, id))]);
+    encode_tlv(0x31, &attribute)
+}
+
+// Repeats `bytes` to fill whole blocks, as the key derivation pads its inputs
+fn fill_blocks(bytes: &[u8]) -> Vec<u8> {
+    let len = bytes.len().div_ceil(BLOCK) * BLOCK;
+    bytes.iter().copied().cycle().take(len).collect()
+}
+
+// Derives the MAC key from the password as in RFC 7292 appendix B.2, with the
+// password as a NUL-terminated BMPString. The key is as long as one digest, so
+// only the first block of the derivation is needed.
+fn mac_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut bmp: Vec<u8> = password.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    bmp.extend_from_slice(&[0, 0]);
+    let mut digest: [u8; 32] = Sha256::new()
+        .chain_update([3u8; BLOCK])
+        .chain_update(fill_blocks(salt))
+        .chain_update(fill_blocks(&bmp))
+        .finalize()
+        .into();
+    for _ in 1..iterations {
+        digest = Sha256::digest(digest).into();
+    }
+    digest
+}
+
+/// Packages the first ECDSA key of `keybox` and its certificate chain as a
+/// PKCS#12 (`.p12`) bundle, for tools such as OpenSSL that take a key and chain
+/// in one file.
+///
+/// **The private key is not encrypted.** It is stored as a plain PKCS#8 key
+/// bag, as `openssl pkcs12 -export -keypbe NONE -certpbe NONE` would write it,
+/// and anyone with the bundle can read it without `password`. The password
+/// only keys the HMAC-SHA256 integrity MAC over the contents, so keep the
+/// output as private as the keybox itself. The leaf certificate carries the
+/// key's `localKeyId`. The MAC salt is derived from the contents, so the same
+/// keybox and password always give the same bytes.
+///
+/// Fails with [`KeyboxError::MalformedKeybox`] if the keybox has no ECDSA key
+/// with both a private key and certificates, and with
+/// [`KeyboxError::InvalidPrivateKey`] if the key cannot be converted to PKCS#8.
+pub fn to_unencrypted_pkcs12(keybox: &Keybox, password: &str) -> Result<Vec<u8>, KeyboxError> {
+    let (key, private_key) = keybox
+        .ec_key()
+        .and_then(|key| Some((key, key.private_key_bytes()?)))
+        .filter(|(key, _)| !key.certificates.is_empty())
+        .ok_or_else(|| {
+            KeyboxError::MalformedKeybox("a PKCS#12 bundle needs an ECDSA key with certificates".to_string())
+        })?;
+    let format = key.private_key_format.clone().or_else(|| detect_format(private_key));
+    let pkcs8 = to_pkcs8(private_key, &format.unwrap_or(PrivateKeyFormat::Sec1))?;
+
+    let key_id: [u8; 32] = Sha256::digest(&key.certificates[0]).into();
+    let mut bags = Vec::new();
+    bags.extend(sequence(&[KEY_BAG, &explicit(&pkcs8), &local_key_id(&key_id)]));
+    for (i, cert) in key.certificates.iter().enumerate() {
+        let cert_bag = sequence(&[X509_CERTIFICATE, &explicit(&encode_tlv(0x04, cert))]);
+        let attributes = if i == 0 { local_key_id(&key_id) } else { Vec::new() };
+        bags.extend(sequence(&[CERT_BAG, &explicit(&cert_bag), &attributes]));
+    }
+    let data = |content: &[u8]| sequence(&[ID_DATA, &explicit(&encode_tlv(0x04, content))]);
+    let auth_safe = sequence(&[&data(&encode_tlv(0x30, &bags))]);
+
+    let salt = &Sha256::digest(&auth_safe)[..16];
+    let mac_key = hmac::Key::new(hmac::HMAC_SHA256, &mac_key(password, salt, MAC_ITERATIONS));
+    let mac = hmac::sign(&mac_key, &auth_safe);
+    let digest_info = sequence(&[&sequence(&[SHA256, DER_NULL]), &encode_tlv(0x04, mac.as_ref())]);
+    let mac_data = sequence(&[&digest_info, &encode_tlv(0x04, salt), &integer(MAC_ITERATIONS)]);
+    Ok(sequence(&[&integer(3), &data(&auth_safe), &mac_data]))
+}
@@ -0,0 +1,123 @@
+// src/schema.rs
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::error::KeyboxError;
+use crate::input::open_input;
+
+// Elements allowed directly inside each element. Elements not listed here hold
+// text only.
+const CHILDREN: &[(&str, &[&str])] = &[
+    ("AndroidAttestation", &["NumberOfKeyboxes", "Keybox"]),
+    ("Keybox", &["NumberOfKeys", "Key", "Signature"]),
+    ("Key", &["Algorithm", "PrivateKey", "CertificateChain"]),
+    ("CertificateChain", &["NumberOfCertificates", "Certificate"]),
+];
+
+// Elements that may repeat, which are numbered in paths, e.g. `Key[2]`. Any other
+// element may appear at most once in its parent.
+const REPEATED: &[&str] = &["Keybox", "Key", "PrivateKey", "Certificate"];
+
+fn allowed_children(element: &str) -> Option<&'static [&'static str]> {
+    CHILDREN.iter().find(|(parent, _)| *parent == element).map(|(_, children)| *children)
+}
+
+// An open element, its segment of the path, whether its structure is checked and
+// how many of each child it has had so far
+struct Open {
+    segment: String,
+    name: String,
+    checked: bool,
+    counts: Vec<(String, usize)>,
+}
+
+fn path(stack: &[Open]) -> String {
+    stack.iter().map(|open| format!("/{}", open.segment)).collect()
+}
+
+/// Checks the keybox XML file at `path`; see [`validate_structure_reader`].
+pub fn validate_structure(path: &Path) -> Result<(), KeyboxError> {
+    validate_structure_reader(BufReader::new(File::open(path)?))
+}
+
+/// Checks that keybox XML from `reader` has the expected element structure:
+/// `AndroidAttestation` holding `NumberOfKeyboxes` and `Keybox` elements, each
+/// keybox holding `NumberOfKeys`, `Key` and `Signature`, each key holding
+/// `Algorithm`, `PrivateKey` and a `CertificateChain` of
+/// `NumberOfCertificates` and `Certificate` elements. Only `Keybox`, `Key`,
+/// `PrivateKey`, whose repeats the parser keeps as further private keys, and
+/// `Certificate` may repeat.
+///
+/// As when parsing, only `AndroidAttestation` elements, wherever they are, and
+/// a `Keybox` root are checked, so a keybox embedded in a larger document
+/// passes whatever surrounds it. A document with neither fails at its root.
+///
+/// The parser itself skips whatever it does not expect, so this is an optional
+/// stricter pass. The first element out of place, or text where only elements
+/// belong, is returned as [`KeyboxError::MalformedKeybox`] with its path, e.g.
+/// `/AndroidAttestation/Keybox[1]/Key[2]/Certificate`. Namespace prefixes are
+/// ignored, as when parsing.
+pub fn validate_structure_reader<R: Read>(reader: R) -> Result<(), KeyboxError> {
+    let mut stack: Vec<Open> = Vec::new();
+    let mut root: Option<String> = None;
+    let mut checked_any = false;
+    for event in EventReader::new(open_input(reader)?) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                let name = name.local_name;
+                root.get_or_insert_with(|| name.clone());
+                let (checked, allowed, index) = match stack.last_mut() {
+                    Some(parent) if parent.checked => {
+                        let index = match parent.counts.iter_mut().find(|(seen, _)| *seen == name) {
+                            Some((_, count)) => {
+                                *count += 1;
+                                *count
+                            }
+                            None => {
+                                parent.counts.push((name.clone(), 1));
+                                1
+                            }
+                        };
+                        let children = allowed_children(&parent.name).unwrap_or_default();
+                        (true, children.contains(&name.as_str()), index)
+                    }
+                    // Outside the checked subtrees only where one starts matters
+                    parent => {
+                        let checked = name == "AndroidAttestation" || (name == "Keybox" && parent.is_none());
+                        (checked, true, 1)
+                    }
+                };
+                checked_any |= checked;
+                let repeated = checked && REPEATED.contains(&name.as_str());
+                let segment = if repeated { format!("{}[{}]", name, index) } else { name.clone() };
+                stack.push(Open {
+                    segment,
+                    name,
+                    checked,
+                    counts: Vec::new(),
+                });
+                // Only the numbered elements may appear more than once in their parent
+                if !allowed || (index > 1 && !repeated) {
+                    return Err(KeyboxError::MalformedKeybox(format!("unexpected element at {}", path(&stack))));
+                }
+            }
+            XmlEvent::EndElement { .. } => {
+                stack.pop();
+            }
+            // Text is only expected in elements that hold no other elements
+            XmlEvent::Characters(text) | XmlEvent::CData(text)
+                if !text.trim().is_empty()
+                    && stack.last().is_some_and(|open| open.checked && allowed_children(&open.name).is_some()) =>
+            {
+                return Err(KeyboxError::MalformedKeybox(format!("unexpected text in {}", path(&stack))));
+            }
+            _ => {}
+        }
+    }
+    match root {
+        Some(root) if !checked_any => Err(KeyboxError::MalformedKeybox(format!("unexpected element at /{}", root))),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,63 @@
+// src/writer.rs
+use base64::engine::general_purpose::STANDARD;
+use xml::escape::escape_str_attribute;
+
+use crate::model::{KeyAlgorithm, KeyEntry, Keybox, PrivateKeyFormat};
+use crate::pem::{der_to_base64, der_to_pem};
+
+// PEM label for a private key, falling back to the usual format of its algorithm
+fn private_key_label(key: &KeyEntry) -> String {
+    match &key.private_key_format {
+        Some(format) => format.label().to_string(),
+        None if key.algorithm == KeyAlgorithm::Rsa => PrivateKeyFormat::Pkcs1.label().to_string(),
+        None => PrivateKeyFormat::Sec1.label().to_string(),
+    }
+}
+
+/// Serializes `keybox` as a canonical keybox document: the `<AndroidAttestation>`
+/// wrapper with `NumberOfKeyboxes` and `NumberOfCertificates` filled in, lowercase
+/// `algorithm` attributes, the format version if one was declared, and every
+/// certificate and private key as a PEM block.
+///
+/// [`parse_keybox_reader`](crate::parse_keybox_reader) reads the output back to
+/// the same keys, so this also normalizes keyboxes written in other styles, e.g.
+/// with headerless base64 or `algorithm="EC"`.
+pub fn to_xml(keybox: &Keybox) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n");
+    match &keybox.format_version {
+        Some(version) => xml.push_str(&format!("<AndroidAttestation version=\"{}\">\n", escape_str_attribute(version))),
+        None => xml.push_str("<AndroidAttestation>\n"),
+    }
+    xml.push_str("<NumberOfKeyboxes>1</NumberOfKeyboxes>\n");
+    match &keybox.device_id {
+        Some(device_id) => xml.push_str(&format!("<Keybox DeviceID=\"{}\">\n", escape_str_attribute(device_id))),
+        None => xml.push_str("<Keybox>\n"),
+    }
+    for key in &keybox.keys {
+        xml.push_str(&format!("<Key algorithm=\"{}\"", key.algorithm.as_str()));
+        for (name, value) in &key.attributes {
+            xml.push_str(&format!(" {}=\"{}\"", name, escape_str_attribute(value)));
+        }
+        xml.push_str(">\n");
+        for private_key in key.private_keys() {
+            let pem = der_to_pem(private_key, &private_key_label(key));
+            xml.push_str(&format!("<PrivateKey format=\"pem\">\n{}</PrivateKey>\n", pem));
+        }
+        xml.push_str("<CertificateChain>\n");
+        xml.push_str(&format!("<NumberOfCertificates>{}</NumberOfCertificates>\n", key.certificates.len()));
+        for cert in &key.certificates {
+            let pem = der_to_pem(cert, "CERTIFICATE");
+            xml.push_str(&format!("<Certificate format=\"pem\">\n{}</Certificate>\n", pem));
+        }
+        xml.push_str("</CertificateChain>\n</Key>\n");
+    }
+    if let Some(signature) = &keybox.signature {
+        xml.push_str(&format!(
+            "<Signature algorithm=\"{}\">{}</Signature>\n",
+            escape_str_attribute(&signature.algorithm),
+            der_to_base64(&signature.value, &STANDARD)
+        ));
+    }
+    xml.push_str("</Keybox>\n</AndroidAttestation>\n");
+    xml
+}
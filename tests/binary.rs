@@ -0,0 +1,66 @@
+#![cfg(feature = "std")]
+
+use keybox_parser::{from_binary, parse_keybox_reader, to_binary, Keybox, KeyboxError};
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+
+fn is_malformed(bytes: &[u8]) -> bool {
+    matches!(from_binary(bytes), Err(KeyboxError::MalformedKeybox(_)))
+}
+
+#[test]
+fn round_trips_through_binary() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let bytes = to_binary(&keybox).unwrap();
+    let decoded = from_binary(&bytes).unwrap();
+
+    assert_eq!(to_binary(&decoded).unwrap(), bytes);
+    assert_eq!(decoded.device_id, keybox.device_id);
+    for (decoded, key) in decoded.keys.iter().zip(&keybox.keys) {
+        assert_eq!(decoded.algorithm, key.algorithm);
+        assert_eq!(decoded.certificates, key.certificates);
+        assert_eq!(decoded.private_key, key.private_key);
+    }
+    assert_eq!(decoded.keys.len(), keybox.keys.len());
+
+    let empty = to_binary(&Keybox::default()).unwrap();
+    assert_eq!(empty, b"KBOX\x01\0\0\0\0\0\0\0\0");
+    assert_eq!(from_binary(&empty).unwrap().device_id, None);
+    assert_eq!(to_binary(&from_binary(&empty).unwrap()).unwrap(), empty);
+}
+
+#[test]
+fn writes_the_documented_layout() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let bytes = to_binary(&keybox).unwrap();
+    let ec = keybox.ec_key().unwrap();
+
+    let mut expected = b"KBOX\x01\0\0\0\x0btest-device".to_vec();
+    expected.extend_from_slice(&(keybox.keys.len() as u32).to_be_bytes());
+    expected.push(0);
+    expected.extend_from_slice(&1u32.to_be_bytes());
+    expected.extend_from_slice(&(ec.certificates[0].len() as u32).to_be_bytes());
+    expected.extend_from_slice(&ec.certificates[0]);
+    let private_key = ec.private_key_bytes().unwrap();
+    expected.extend_from_slice(&(private_key.len() as u32).to_be_bytes());
+    expected.extend_from_slice(private_key);
+    assert!(bytes.starts_with(&expected));
+}
+
+#[test]
+fn rejects_malformed_binary() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let bytes = to_binary(&keybox).unwrap();
+
+    for len in 0..bytes.len() {
+        assert!(is_malformed(&bytes[..len]), "accepted {} of {} bytes", len, bytes.len());
+    }
+    assert!(is_malformed(&[bytes.as_slice(), &[0]].concat()));
+    assert!(is_malformed(&[b"KBOY", &bytes[4..]].concat()));
+    assert!(is_malformed(&[b"KBOX\x02", &bytes[5..]].concat()));
+    // The first key's algorithm byte follows the device id and the key count
+    let mut unknown = bytes.clone();
+    unknown[4 + 1 + 4 + "test-device".len() + 4] = 2;
+    assert!(is_malformed(&unknown));
+    assert!(is_malformed(b"KBOX\x01\0\0\0\x01\xff\0\0\0\0"));
+}
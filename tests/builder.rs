@@ -0,0 +1,121 @@
+#![cfg(feature = "std")]
+
+use keybox_parser::key::detect_format;
+use keybox_parser::{
+    parse_keybox_reader, parse_keybox_with, BodyEncoding, KeyAlgorithm, KeyboxBuilder, KeyboxError, MergePolicy,
+    ParseOptions, PrivateKeyFormat,
+};
+
+const EC_CERTIFICATE: &[u8] = include_bytes!("fixtures/ec_certificate.der");
+const EC_PRIVATE_KEY: &[u8] = include_bytes!("fixtures/ec_private_key.der");
+const RSA_CERTIFICATE: &[u8] = include_bytes!("fixtures/rsa_certificate.der");
+
+#[test]
+fn builds_keybox_that_parses_back() {
+    let builder = KeyboxBuilder::new()
+        .device_id("built <device>")
+        .add_ec_certificate(EC_CERTIFICATE)
+        .ec_private_key(EC_PRIVATE_KEY)
+        .add_rsa_certificate(RSA_CERTIFICATE);
+    let keybox = builder.clone().build();
+    assert_eq!(keybox.ec_key().unwrap().private_key_format, Some(PrivateKeyFormat::Sec1));
+
+    let parsed = parse_keybox_reader(builder.to_xml().as_bytes()).unwrap().remove(0);
+    assert_eq!(parsed.device_id.as_deref(), Some("built <device>"));
+    assert_eq!(parsed.keys.len(), 2);
+    for (parsed, built) in parsed.keys.iter().zip(&keybox.keys) {
+        assert_eq!(parsed.algorithm, built.algorithm);
+        assert_eq!(parsed.certificates, built.certificates);
+        assert_eq!(parsed.private_key, built.private_key);
+        assert_eq!(parsed.private_key_format, built.private_key_format);
+    }
+}
+
+#[test]
+fn detects_private_key_formats() {
+    let pkcs8 = keybox_parser::key::to_pkcs8(EC_PRIVATE_KEY, &PrivateKeyFormat::Sec1).unwrap();
+    assert_eq!(detect_format(EC_PRIVATE_KEY), Some(PrivateKeyFormat::Sec1));
+    assert_eq!(detect_format(&pkcs8), Some(PrivateKeyFormat::Pkcs8));
+    assert_eq!(detect_format(EC_CERTIFICATE), None);
+}
+
+#[test]
+fn merges_private_keys_with_chains_from_another_keybox() {
+    let mut keybox = KeyboxBuilder::new().device_id("split").ec_private_key(EC_PRIVATE_KEY).build();
+    let chains = KeyboxBuilder::new()
+        .add_ec_certificate(EC_CERTIFICATE)
+        .add_rsa_certificate(RSA_CERTIFICATE)
+        .build();
+    keybox.merge(chains, MergePolicy::Reject).unwrap();
+
+    assert_eq!(keybox.device_id.as_deref(), Some("split"));
+    assert_eq!(keybox.keys.len(), 2);
+    let ec = keybox.ec_key().unwrap();
+    assert_eq!(ec.certificates, vec![EC_CERTIFICATE.to_vec()]);
+    assert_eq!(ec.private_key_bytes(), Some(EC_PRIVATE_KEY));
+    assert_eq!(ec.private_key_format, Some(PrivateKeyFormat::Sec1));
+    assert_eq!(keybox.keys[1].algorithm, KeyAlgorithm::Rsa);
+}
+
+#[test]
+fn rejects_conflicting_private_keys_unless_told_otherwise() {
+    let pkcs8 = keybox_parser::key::to_pkcs8(EC_PRIVATE_KEY, &PrivateKeyFormat::Sec1).unwrap();
+    let mut keybox = KeyboxBuilder::new().ec_private_key(EC_PRIVATE_KEY).build();
+    let other = KeyboxBuilder::new().ec_private_key(pkcs8.clone()).add_ec_certificate(EC_CERTIFICATE).build();
+
+    match keybox.merge(other.clone(), MergePolicy::Reject) {
+        Err(KeyboxError::MalformedKeybox(reason)) => {
+            assert_eq!(reason, "cannot merge keyboxes: key 1 (EC) has two different private keys");
+        }
+        other => panic!("expected a merge conflict, got {:?}", other),
+    }
+    assert!(keybox.ec_key().unwrap().certificates.is_empty());
+
+    keybox.merge(other, MergePolicy::PreferOther).unwrap();
+    let ec = keybox.ec_key().unwrap();
+    assert_eq!(ec.private_key_bytes(), Some(&pkcs8[..]));
+    assert_eq!(ec.private_key_format, Some(PrivateKeyFormat::Pkcs8));
+}
+
+#[test]
+fn moves_raw_base64_and_encoding_with_the_chain() {
+    let options = ParseOptions {
+        keep_raw_base64: true,
+        ..ParseOptions::default()
+    };
+    let parsed = parse_keybox_with(include_str!("fixtures/keybox.xml").as_bytes(), &options).unwrap();
+    let mut keybox = parsed.keyboxes.into_iter().next().unwrap();
+    let own = keybox.rsa_key().unwrap().clone();
+    assert_eq!((own.raw_certificates.len(), own.encoding), (1, Some(BodyEncoding::Pem)));
+
+    let mut other = KeyboxBuilder::new().add_rsa_certificate(EC_CERTIFICATE).build();
+    other.keys[0].raw_certificates = vec!["MIIB".to_string()];
+    other.keys[0].encoding = Some(BodyEncoding::Base64);
+    keybox.merge(other.clone(), MergePolicy::PreferOther).unwrap();
+    let rsa = keybox.rsa_key().unwrap();
+    assert_eq!(rsa.certificates, vec![EC_CERTIFICATE.to_vec()]);
+    assert_eq!(rsa.raw_certificates, ["MIIB"]);
+    assert_eq!(rsa.encoding, Some(BodyEncoding::Base64));
+    // The private key stays, and so does the EC key with its chain
+    assert_eq!(rsa.private_key, own.private_key);
+    assert_eq!(keybox.ec_key().unwrap().encoding, Some(BodyEncoding::Pem));
+
+    // A chain that is kept keeps its own raw base64
+    let mut keybox = KeyboxBuilder::new().add_rsa_certificate(EC_CERTIFICATE).build();
+    other.keys[0].raw_certificates.clear();
+    keybox.keys[0].raw_certificates = vec!["kept".to_string()];
+    keybox.merge(other, MergePolicy::Reject).unwrap();
+    assert_eq!(keybox.rsa_key().unwrap().raw_certificates, ["kept"]);
+}
+
+#[cfg(feature = "verify")]
+#[test]
+fn rejects_a_private_key_for_another_leaf() {
+    let attestation_certificate = include_bytes!("fixtures/ec_attestation_certificate.der");
+    let mut keybox = KeyboxBuilder::new().ec_private_key(EC_PRIVATE_KEY).build();
+    let chain = KeyboxBuilder::new().add_ec_certificate(attestation_certificate.to_vec()).build();
+    match keybox.merge(chain, MergePolicy::Reject) {
+        Err(KeyboxError::KeyMismatch { context }) => assert_eq!(context, "key 1 (EC)"),
+        other => panic!("expected a key mismatch, got {:?}", other),
+    }
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "x509")]
+
+use keybox_parser::certificate::{chain_report, choose_freshest_keys, choose_keys_by, validity, KeyChoice};
+use keybox_parser::{parse_keybox_reader, KeyAlgorithm, Keybox};
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+const EC_CERTIFICATE: &[u8] = include_bytes!("fixtures/ec_certificate.der");
+const EC_ATTESTATION_CERTIFICATE: &[u8] = include_bytes!("fixtures/ec_attestation_certificate.der");
+
+// The fixture keybox with a second EC key after the RSA key, whose leaf was issued
+// and expires about an hour after the first key's
+fn two_ec_keys() -> Keybox {
+    let mut keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let mut second = keybox.ec_key().unwrap().clone();
+    second.certificates = vec![EC_ATTESTATION_CERTIFICATE.to_vec()];
+    keybox.keys.push(second);
+    keybox
+}
+
+fn ec_leaf(keybox: &Keybox) -> &[u8] {
+    keybox.ec_key().unwrap().leaf().unwrap()
+}
+
+#[test]
+fn chooses_the_valid_key_that_expires_last() {
+    let (not_before, not_after) = validity(EC_ATTESTATION_CERTIFICATE).unwrap();
+    let mut keybox = two_ec_keys();
+    let choices = choose_freshest_keys(&mut keybox, not_before + 86400).unwrap();
+    assert_eq!(ec_leaf(&keybox), EC_ATTESTATION_CERTIFICATE);
+    assert_eq!(keybox.keys[1].algorithm, KeyAlgorithm::Ecdsa);
+    assert_eq!(keybox.keys[2].algorithm, KeyAlgorithm::Rsa);
+    assert_eq!(
+        choices[0],
+        KeyChoice {
+            algorithm: KeyAlgorithm::Ecdsa,
+            chosen: Some(2),
+            score: Some(not_after),
+            keys: 2,
+            unusable: 0,
+        }
+    );
+    assert_eq!(choices[1].chosen, Some(1));
+}
+
+#[test]
+fn skips_expired_and_not_yet_valid_keys() {
+    let (_, expired) = validity(EC_CERTIFICATE).unwrap();
+    let mut keybox = two_ec_keys();
+    let choices = choose_freshest_keys(&mut keybox, expired + 1).unwrap();
+    assert_eq!(ec_leaf(&keybox), EC_ATTESTATION_CERTIFICATE);
+    assert_eq!((choices[0].chosen, choices[0].unusable), (Some(2), 1));
+
+    let (not_before, _) = validity(EC_CERTIFICATE).unwrap();
+    let mut keybox = two_ec_keys();
+    let choices = choose_freshest_keys(&mut keybox, not_before - 1).unwrap();
+    assert_eq!(ec_leaf(&keybox), EC_CERTIFICATE);
+    assert_eq!((choices[0].chosen, choices[0].unusable), (None, 2));
+}
+
+#[test]
+fn keeps_the_earlier_key_on_a_tie() {
+    let mut keybox = two_ec_keys();
+    let choices = choose_keys_by(&mut keybox, |_| Ok(Some(0))).unwrap();
+    assert_eq!(ec_leaf(&keybox), EC_CERTIFICATE);
+    assert_eq!(choices[0].chosen, Some(1));
+}
+
+#[test]
+fn reports_chain_links_and_unreadable_certificates() {
+    let mut keybox = two_ec_keys();
+    let key = &mut keybox.keys[0];
+    key.certificates = vec![EC_ATTESTATION_CERTIFICATE.to_vec(), EC_CERTIFICATE.to_vec(), vec![0x30, 0x00]];
+
+    let report = chain_report(key);
+    assert_eq!(report.links.len(), 3);
+    let leaf = report.links[0].certificate.as_ref().unwrap();
+    assert_eq!(leaf.validity, validity(EC_ATTESTATION_CERTIFICATE).unwrap());
+    assert_eq!(report.links[0].issued_by_next, Some(false));
+    assert!(report.links[1].certificate.as_ref().unwrap().self_issued);
+    assert_eq!(report.links[1].issued_by_next, None);
+    assert!(report.links[2].certificate.is_err());
+    assert!(!report.is_linked());
+
+    let text = report.to_string();
+    assert_eq!(text.lines().count(), 3);
+    assert!(text.contains("; NOT issued by certificate 2\n"));
+    assert!(text.contains("certificate 3: unreadable: "));
+
+    key.certificates.truncate(1);
+    assert!(chain_report(key).is_linked());
+    key.certificates.clear();
+    assert!(chain_report(key).links.is_empty());
+    assert!(!chain_report(key).is_linked());
+}
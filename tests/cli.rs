@@ -0,0 +1,130 @@
+#![cfg(feature = "std")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+
+// A fresh directory holding `files`, given as `(name, contents)`
+fn batch_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("keybox-batch-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("keyboxes")).unwrap();
+    for (file, contents) in files {
+        std::fs::write(dir.join("keyboxes").join(file), contents).unwrap();
+    }
+    dir
+}
+
+fn keybox2rs(batch: &Path, output: &Path, strict: bool) -> bool {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_keybox2rs"));
+    command.arg("--batch").arg(batch).arg("--output").arg(output);
+    if strict {
+        command.arg("--strict");
+    }
+    command.output().unwrap().status.success()
+}
+
+#[test]
+fn converts_a_directory_of_keyboxes() {
+    let dir = batch_dir("directory", &[("Device-A.xml", FIXTURE), ("b.xml", FIXTURE), ("broken.xml", "<Keybox")]);
+    let output = dir.join("generated");
+    assert!(keybox2rs(&dir.join("keyboxes"), &output, false));
+
+    let index = std::fs::read_to_string(output.join("mod.rs")).unwrap();
+    assert!(index.ends_with("\npub mod device_a;\npub mod b;\n"));
+    assert!(!output.join("broken").exists());
+    let device = std::fs::read_to_string(output.join("device_a/mod.rs")).unwrap();
+    assert!(device.contains("pub const DEVICE_ID: &str = \"test-device\";"));
+
+    assert!(!keybox2rs(&dir.join("keyboxes"), &dir.join("strict"), true));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn converts_keyboxes_listed_in_a_manifest() {
+    let dir = batch_dir("manifest", &[("a.xml", FIXTURE)]);
+    std::fs::write(dir.join("keyboxes.txt"), "# Fleet keyboxes\nfirst = \"keyboxes/a.xml\"\n").unwrap();
+    let output = dir.join("generated");
+    assert!(keybox2rs(&dir.join("keyboxes.txt"), &output, false));
+
+    assert!(std::fs::read_to_string(output.join("mod.rs")).unwrap().ends_with("\npub mod first;\n"));
+    assert!(output.join("first/mod.rs").is_file());
+
+    // The manifest is a line format, not TOML
+    for line in ["first = \"keyboxes/a.xml\" # device", "first = 'keyboxes/a.xml'", "[keyboxes]"] {
+        std::fs::write(dir.join("keyboxes.txt"), format!("{}\n", line)).unwrap();
+        assert!(!keybox2rs(&dir.join("keyboxes.txt"), &dir.join("rejected"), false), "accepted {:?}", line);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn keeps_keywords_out_of_module_names() {
+    let dir = batch_dir("keywords", &[("type.xml", FIXTURE), ("Self.xml", FIXTURE)]);
+    let output = dir.join("generated");
+    assert!(keybox2rs(&dir.join("keyboxes"), &output, false));
+    let index = std::fs::read_to_string(output.join("mod.rs")).unwrap();
+    assert!(index.ends_with("\npub mod keybox_self;\npub mod keybox_type;\n"), "{}", index);
+
+    std::fs::write(dir.join("keyboxes.txt"), "mod = \"keyboxes/type.xml\"\n").unwrap();
+    assert!(!keybox2rs(&dir.join("keyboxes.txt"), &dir.join("manifest"), false));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// Runs keybox2rs with `args`, feeding `input` to its stdin, and returns its exit
+// status and stdout
+fn keybox2rs_stdin(args: &[&str], input: &[u8]) -> (bool, String) {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_keybox2rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (output.status.success(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn converts_stdin_to_stdout() {
+    for args in [&[][..], &["-"], &["--input", "-", "--output", "-"]] {
+        let (success, output) = keybox2rs_stdin(args, FIXTURE.as_bytes());
+        assert!(success);
+        assert!(output.contains("// Source: <stdin>\n"));
+        assert!(output.contains("pub const DEVICE_ID: &str = \"test-device\";"));
+    }
+
+    let (success, output) = keybox2rs_stdin(&["-"], b"<Keybox");
+    assert!(!success);
+    assert!(output.is_empty());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn converts_gzip_compressed_stdin() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(FIXTURE.as_bytes()).unwrap();
+    let (success, output) = keybox2rs_stdin(&["-"], &encoder.finish().unwrap());
+    assert!(success);
+    assert!(output.contains("pub const DEVICE_ID: &str = \"test-device\";"));
+}
+
+#[cfg(feature = "x509")]
+#[test]
+fn reports_chain_structure_when_checking() {
+    let (success, output) = keybox2rs_stdin(&["--check"], FIXTURE.as_bytes());
+    assert!(success);
+    assert!(output.contains("ok   keybox 1 EC certificate 1\n"));
+    assert!(output.contains(
+        "\nkeybox 1 EC chain:\n  certificate 1: subject CN=Keybox Parser Test EC; issuer CN=Keybox Parser Test EC; valid "
+    ));
+    assert!(output.contains("\nkeybox 1 RSA chain:\n"));
+    assert!(output.ends_with("; self-issued\n"));
+}
@@ -0,0 +1,437 @@
+#![cfg(feature = "std")]
+
+use keybox_parser::codegen::{
+    write_device_constants, write_named_constants, write_rust_constants, Algorithms, CodegenOptions, KeyboxSource,
+    NamedKeybox,
+};
+use keybox_parser::{parse_keybox_reader, Keybox, KeyboxError};
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+
+fn generate(keybox: &Keybox, algorithms: Algorithms) -> String {
+    let options = CodegenOptions {
+        algorithms,
+        ..CodegenOptions::default()
+    };
+    generate_with(keybox, &options)
+}
+
+fn generate_with(keybox: &Keybox, options: &CodegenOptions) -> String {
+    let mut output = Vec::new();
+    write_rust_constants(&mut output, keybox, options).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn writes_module_per_algorithm() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Both);
+
+    assert!(output.contains("pub const DEVICE_ID: &str = \"test-device\";"));
+    assert!(output.contains("pub mod ec {"));
+    assert!(output.contains("pub mod rsa {"));
+    assert!(output.contains("    pub const CERTIFICATE_COUNT: usize = 1;"));
+    assert!(output.contains("    pub const CERTIFICATE_CHAIN: &[&[u8]] = &[CERTIFICATE_1];"));
+    assert!(output.contains("    pub const ROOT_CERTIFICATE: &[u8] = CERTIFICATE_1;"));
+    assert!(output.contains("pub const EC_CERTIFICATE_1: &[u8] = ec::CERTIFICATE_1;"));
+    assert!(output.contains("pub const EC_PRIVATE_KEY: &[u8] = ec::PRIVATE_KEY;"));
+    assert!(!output.contains("pub mod ec_2"));
+    // Only the constants that were flat before modules keep deprecated aliases
+    assert_eq!(output.matches("#[deprecated").count(), 4);
+    assert!(!output.contains("pub const RSA_CERTIFICATE_1"));
+    assert!(!output.contains("pub const EC_CERTIFICATE_CHAIN"));
+}
+
+#[test]
+fn names_each_key_algorithm() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Both);
+
+    let ty = "keybox_parser::KeyAlgorithm";
+    assert!(output.contains(&format!("\n    pub const KEY_ALGORITHM: {} = {}::Ecdsa;", ty, ty)));
+    assert!(output.contains(&format!("\n    pub const KEY_ALGORITHM: {} = {}::Rsa;", ty, ty)));
+    assert!(!output.contains("EC_KEY_ALGORITHM"));
+}
+
+// The library's own constants name the enum the same way
+#[cfg(feature = "ec")]
+#[test]
+fn embeds_key_algorithm_in_the_library() {
+    assert_eq!(keybox_parser::ec_constants::ec::KEY_ALGORITHM, keybox_parser::KeyAlgorithm::Ecdsa);
+}
+
+#[test]
+fn writes_numbered_module_per_extra_key() {
+    let mut keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let second = keybox.ec_key().unwrap().clone();
+    keybox.keys.push(second);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+
+    assert!(output.contains("pub mod ec {"));
+    assert!(output.contains("pub mod ec_2 {"));
+    assert!(!output.contains("EC_2_"));
+}
+
+#[test]
+fn writes_empty_constants_without_keybox() {
+    let output = generate(&Keybox::default(), Algorithms::Ecdsa);
+
+    assert!(output.contains("pub const DEVICE_ID: &str = \"\";"));
+    assert!(output.contains("    pub const CERTIFICATE_3: &[u8] = &[];"));
+    assert!(output.contains("    pub const CERTIFICATE_CHAIN: &[&[u8]] = &[];"));
+    assert!(output.contains("    pub const LEAF_CERTIFICATE: &[u8] = &[];"));
+    assert!(output.contains("    pub const ROOT_CERTIFICATE: &[u8] = &[];"));
+    assert!(output.contains("    pub const PRIVATE_KEY: &[u8] = &[];"));
+    assert!(!output.contains("pub mod rsa"));
+}
+
+#[test]
+fn records_keybox_source() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+    assert!(output.contains("\npub const KEYBOX_SOURCE: &str = \"\";"));
+    assert!(output.contains("\npub const GENERATED_AT: u64 = 0;"));
+    assert!(output.contains("\npub const KEYBOX_PRESENT: bool = false;"));
+
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        source: Some(KeyboxSource::new("keyboxes/test.xml", FIXTURE.as_bytes())),
+        timestamp: Some(1700000000),
+        ..CodegenOptions::default()
+    };
+    let output = generate_with(&keybox, &options);
+    let sha256 = "ac0f322a593d5070cbf1596175efb9db9bd0c96adc6c7b52e96fbf5d26a612b1";
+    assert!(output.contains("// Source: keyboxes/test.xml\n"));
+    assert!(output.contains(&format!("// SHA-256: {}\n", sha256)));
+    assert!(output.contains(&format!("pub const KEYBOX_SHA256_HEX: &str =\n    \"{}\";", sha256)));
+    assert!(output.contains(&format!("\npub const GENERATOR_VERSION: &str = \"{}\";", env!("CARGO_PKG_VERSION"))));
+    assert!(output.contains("\npub const GENERATED_AT: u64 = 1700000000;"));
+    assert!(output.contains("\npub const KEYBOX_PRESENT: bool = true;"));
+}
+
+#[test]
+fn prefixes_top_level_names() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        const_prefix: Some("PIXEL6".to_string()),
+        ..CodegenOptions::default()
+    };
+    let output = generate_with(&keybox, &options);
+
+    assert!(output.contains("\npub const PIXEL6_DEVICE_ID: &str = \"test-device\";"));
+    assert!(output.contains("\npub const PIXEL6_KEYBOX_SOURCE: &str = \"\";"));
+    assert!(output.contains("\npub mod pixel6_ec {\n    /// `DeviceID` of the keybox, empty if it has none.\n    pub const DEVICE_ID: &str = super::PIXEL6_DEVICE_ID;"));
+    assert!(output.contains("\npub const PIXEL6_EC_CERTIFICATE_1: &[u8] = pixel6_ec::CERTIFICATE_1;"));
+    assert!(!output.contains("\npub const DEVICE_ID"));
+
+    let options = CodegenOptions {
+        const_prefix: Some("6pixel".to_string()),
+        ..options
+    };
+    assert!(write_rust_constants(&mut Vec::new(), &keybox, &options).is_err());
+}
+
+#[test]
+fn writes_keyboxes_side_by_side_by_device_id() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let device = |device_id: &str| Keybox {
+        device_id: Some(device_id.to_string()),
+        ..keybox.clone()
+    };
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        ..CodegenOptions::default()
+    };
+    let mut output = Vec::new();
+    write_device_constants(&mut output, &[device("pixel-6"), device("7a")], &options).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert_eq!(output.matches("// Auto-generated constants").count(), 1);
+    assert!(output.contains("\npub const PIXEL_6_DEVICE_ID: &str = \"pixel-6\";"));
+    assert!(output.contains("\npub mod pixel_6_ec {\n    /// `DeviceID` of the keybox, empty if it has none.\n    pub const DEVICE_ID: &str = super::PIXEL_6_DEVICE_ID;"));
+    assert!(output.contains("\npub const DEVICE_7A_DEVICE_ID: &str = \"7a\";"));
+    assert!(output.contains("\npub const DEVICE_7A_EC_CERTIFICATE_1: &[u8] = device_7a_ec::CERTIFICATE_1;"));
+    assert!(!output.contains("\npub const DEVICE_ID"));
+
+    let options = CodegenOptions {
+        const_prefix: Some("KB".to_string()),
+        ..options
+    };
+    let mut output = Vec::new();
+    write_device_constants(&mut output, &[device("pixel-6")], &options).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("\npub mod kb_pixel_6_ec {"));
+
+    for keyboxes in [vec![device("a-b"), device("a_b")], vec![Keybox::default()]] {
+        let mut output = Vec::new();
+        assert!(write_device_constants(&mut output, &keyboxes, &options).is_err());
+        assert!(output.is_empty());
+    }
+}
+
+#[test]
+fn writes_keyboxes_side_by_side_by_name() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let named = |name: &str, source: Option<KeyboxSource>| NamedKeybox {
+        name: name.to_string(),
+        keybox: keybox.clone(),
+        source,
+    };
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        source: Some(KeyboxSource::new("devices", b"")),
+        ..CodegenOptions::default()
+    };
+    let keyboxes = [named("pixel-6", Some(KeyboxSource::new("pixel-6/keybox.xml", b""))), named("7a", None)];
+    let mut output = Vec::new();
+    write_named_constants(&mut output, &keyboxes, &options).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    // Both have the same DeviceID, which only names the constants' values
+    assert!(output.contains("\npub const PIXEL_6_DEVICE_ID: &str = \"test-device\";"));
+    assert!(output.contains("\npub const DEVICE_7A_DEVICE_ID: &str = \"test-device\";"));
+    assert!(output.contains("\npub const PIXEL_6_KEYBOX_SOURCE: &str = \"pixel-6/keybox.xml\";"));
+    assert!(output.contains("\npub const DEVICE_7A_KEYBOX_SOURCE: &str = \"devices\";"));
+
+    let mut output = Vec::new();
+    assert!(write_named_constants(&mut output, &[named("a-b", None), named("a_b", None)], &options).is_err());
+    assert!(output.is_empty());
+}
+
+#[test]
+fn asserts_constants_are_populated() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        assert_populated: true,
+        ..CodegenOptions::default()
+    };
+    let output = generate_with(&keybox, &options);
+    assert!(output.contains(
+        "\nconst _: () = assert!(!ec::CERTIFICATE_1.is_empty(), \"ec::CERTIFICATE_1 is empty\");\n\
+         const _: () = assert!(!ec::PRIVATE_KEY.is_empty(), \"ec::PRIVATE_KEY is empty\");\n"
+    ));
+    assert!(!output.contains("CERTIFICATE_2.is_empty()"));
+    assert!(!generate(&keybox, Algorithms::Ecdsa).contains("assert!"));
+}
+
+#[test]
+fn groups_array_bytes() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let fingerprint_line = |output: &str| {
+        let start = output.find("    pub const CERTIFICATE_1_SHA256: [u8; 32] = [\n").unwrap();
+        output[start..].lines().nth(1).unwrap().trim().split(' ').count()
+    };
+    assert_eq!(fingerprint_line(&generate(&keybox, Algorithms::Ecdsa)), 15);
+
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        bytes_per_line: Some(8),
+        ..CodegenOptions::default()
+    };
+    assert_eq!(fingerprint_line(&generate_with(&keybox, &options)), 8);
+}
+
+#[cfg(feature = "x509")]
+#[test]
+fn writes_leaf_subject() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+
+    assert!(output.contains("    pub const CERTIFICATE_SUBJECT: &str = \"CN=Keybox Parser Test EC\";"));
+    assert!(output.contains("    pub const CERTIFICATE_SUBJECT_CN: &str = \"Keybox Parser Test EC\";"));
+}
+
+#[cfg(feature = "x509")]
+#[test]
+fn writes_attestation_extension() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+    assert!(output.contains("    pub const ATTESTATION_EXTENSION: &[u8] = &[];"));
+
+    let keybox = keybox_parser::KeyboxBuilder::new()
+        .add_ec_certificate(include_bytes!("fixtures/ec_attestation_certificate.der").to_vec())
+        .build();
+    let output = generate(&keybox, Algorithms::Ecdsa);
+    assert!(output.contains("    pub const ATTESTATION_EXTENSION: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x03];"));
+    assert!(!output.contains("EC_ATTESTATION_EXTENSION"));
+}
+
+#[cfg(feature = "x509")]
+#[test]
+fn writes_certificate_serials() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+
+    assert!(output.contains("    pub const CERTIFICATE_1_SERIAL: &str = \"16948c295ac5273ff718621ba46e5aeec3a512c1\";"));
+    assert!(output.contains("    pub const CERTIFICATE_2_SERIAL: &str = \"\";"));
+}
+
+#[test]
+fn documents_each_constant() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+
+    assert!(output.contains("\n/// `DeviceID` of the keybox, empty if it has none.\npub const DEVICE_ID: &str"));
+    assert!(output.contains(
+        "    /// DER of certificate 1 of the chain of ECDSA key 1 of keybox \"test-device\".\n"
+    ));
+    assert!(output.contains(
+        "    /// DER of certificate 2 of the chain of ECDSA key 1 of keybox \"test-device\"; \
+         empty, as the chain is shorter.\n    pub const CERTIFICATE_2: &[u8] = &[];"
+    ));
+    assert!(output.contains("    /// DER of the private key of ECDSA key 1 of keybox \"test-device\" (EC PRIVATE KEY).\n"));
+    assert!(output.contains("\npub const EC_CERTIFICATE_1: &[u8] = ec::CERTIFICATE_1;"));
+    let output = generate(&Keybox::default(), Algorithms::Ecdsa);
+    assert!(output.contains("    /// DER of the private key of ECDSA key 1 of the keybox; empty.\n"));
+}
+
+#[cfg(feature = "x509")]
+#[test]
+fn documents_certificate_subject_and_validity() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+
+    assert!(output.contains(
+        "    ///\n    /// Subject CN: `Keybox Parser Test EC`.\n    ///\n    \
+         /// Valid from Oct 14 10:22:40 2026 +00:00 to Sep 20 10:22:40 2126 +00:00.\n    pub const CERTIFICATE_1: &[u8]"
+    ));
+}
+
+#[cfg(feature = "x509")]
+#[test]
+fn escapes_line_breaks_in_doc_comments() {
+    // A CN with line breaks must not end the doc comment it is quoted in
+    let der = include_bytes!("fixtures/ec_certificate.der").to_vec();
+    let cn = b"Keybox Parser Test EC";
+    let mut crafted = der.clone();
+    for i in (0..der.len() - cn.len()).filter(|&i| &der[i..i + cn.len()] == cn) {
+        crafted[i..i + cn.len()].copy_from_slice(b"Keybox\nParser\rTest EC");
+    }
+    let keybox = keybox_parser::KeyboxBuilder::new().add_ec_certificate(crafted).build();
+    let output = generate(&keybox, Algorithms::Ecdsa);
+
+    assert!(output.contains("    /// Subject CN: `Keybox\\nParser\\rTest EC`.\n"));
+    assert!(!output.lines().any(|line| line.starts_with("Parser")));
+    assert!(!output.contains('\r'));
+}
+
+#[test]
+fn ignores_namespace_prefixes() {
+    let declared = FIXTURE
+        .replace(
+            "<AndroidAttestation>",
+            "<kb:AndroidAttestation xmlns:kb=\"urn:keybox\" xmlns:ns=\"urn:keybox:key\" xml:lang=\"en\">",
+        )
+        .replace("</AndroidAttestation>", "</kb:AndroidAttestation>");
+    // Prefixes on the keybox elements and the algorithm attribute are matched by
+    // local name
+    let xml = ["Keybox", "Key", "PrivateKey", "CertificateChain", "Certificate", "NumberOfCertificates"]
+        .iter()
+        .fold(declared, |xml, element| {
+            xml.replace(&format!("<{}>", element), &format!("<ns:{}>", element))
+                .replace(&format!("<{} ", element), &format!("<ns:{} ", element))
+                .replace(&format!("</{}>", element), &format!("</ns:{}>", element))
+        })
+        .replace(" algorithm=", " ns:algorithm=");
+
+    let plain = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let namespaced = parse_keybox_reader(xml.as_bytes()).unwrap().remove(0);
+    assert_eq!(generate(&namespaced, Algorithms::Both), generate(&plain, Algorithms::Both));
+
+    // An unprefixed attribute of the same local name is not a duplicate, and wins
+    let both = xml.replace(" ns:algorithm=\"ecdsa\"", " ns:algorithm=\"rsa\" algorithm=\"ecdsa\"");
+    let both = parse_keybox_reader(both.as_bytes()).unwrap().remove(0);
+    assert_eq!(generate(&both, Algorithms::Both), generate(&plain, Algorithms::Both));
+
+    // Undeclared prefixes are not namespace-well-formed XML
+    let undeclared = xml.replace(" xmlns:ns=\"urn:keybox:key\"", "");
+    assert!(matches!(parse_keybox_reader(undeclared.as_bytes()), Err(KeyboxError::Xml { .. })));
+}
+
+#[cfg(feature = "hex")]
+#[test]
+fn writes_certificates_as_hex() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+    let hex: String = keybox.ec_leaf().unwrap().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    assert!(output.contains(&format!("    pub const CERTIFICATE_1_HEX: &str = \"{}\";", hex)));
+    assert!(output.contains("    pub const CERTIFICATE_2_HEX: &str = \"\";"));
+    assert!(!output.contains("EC_CERTIFICATE_1_HEX"));
+}
+
+#[cfg(feature = "arrays")]
+#[test]
+fn writes_fixed_size_arrays() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Ecdsa);
+    let length = keybox.ec_leaf().unwrap().len();
+
+    assert!(output.contains("    pub const CERTIFICATE_1: &[u8] = &CERTIFICATE_1_ARRAY;"));
+    assert!(output.contains(&format!("    pub const CERTIFICATE_1_ARRAY: [u8; {}] = [\n        0x30,", length)));
+    assert!(output.contains("    pub const CERTIFICATE_2: &[u8] = &[];\n\n    /// [`CERTIFICATE_2`] as a fixed-size array.\n    pub const CERTIFICATE_2_ARRAY: [u8; 0] = [];"));
+    assert!(output.contains("    pub const PRIVATE_KEY: &[u8] = &PRIVATE_KEY_ARRAY;"));
+    assert!(!output.contains("EC_CERTIFICATE_1_ARRAY"));
+}
+
+#[cfg(feature = "artifacts")]
+#[test]
+fn lists_embedded_artifacts() {
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let output = generate(&keybox, Algorithms::Both);
+
+    assert!(output.contains(
+        "pub const KEYBOX_ARTIFACTS: &[(&str, &[u8])] = &[\n    (\"ec_cert_1\", ec::CERTIFICATE_1),\n    \
+         (\"ec_private_key\", ec::PRIVATE_KEY),\n    (\"rsa_cert_1\", rsa::CERTIFICATE_1),\n    \
+         (\"rsa_private_key\", rsa::PRIVATE_KEY),\n];"
+    ));
+}
+
+#[test]
+fn creates_missing_output_directories() {
+    let dir = std::env::temp_dir().join(format!("keybox-nested-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let file_path = dir.join("a/b/constants.rs");
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    keybox_parser::codegen::write_rust_constants_file(&file_path, &keybox, &CodegenOptions::default()).unwrap();
+    assert!(std::fs::read_to_string(&file_path).unwrap().contains("pub const DEVICE_ID: &str = \"test-device\";"));
+
+    // A file where a directory is needed cannot be written through
+    let error = keybox_parser::codegen::write_rust_constants_file(
+        &file_path.join("constants.rs"),
+        &keybox,
+        &CodegenOptions::default(),
+    )
+    .unwrap_err();
+    assert!(error.to_string().starts_with(&format!("cannot write {}: ", file_path.display())));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "der-files")]
+#[test]
+fn writes_der_files_next_to_the_constants() {
+    let dir = std::env::temp_dir().join(format!("keybox-der-files-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let options = CodegenOptions {
+        algorithms: Algorithms::Ecdsa,
+        ..CodegenOptions::default()
+    };
+    keybox_parser::codegen::write_rust_constants_file(&dir.join("constants.rs"), &keybox, &options).unwrap();
+
+    let output = std::fs::read_to_string(dir.join("constants.rs")).unwrap();
+    let certificate = match keybox.ec_leaf().unwrap().len() {
+        // With `arrays` the file is included by value
+        length if cfg!(feature = "arrays") => {
+            format!("    pub const CERTIFICATE_1_ARRAY: [u8; {}] = *include_bytes!(\"ec_certificate_1.der\");", length)
+        }
+        _ => "    pub const CERTIFICATE_1: &[u8] = include_bytes!(\"ec_certificate_1.der\");".to_string(),
+    };
+    assert!(output.contains(&certificate));
+    assert!(output.contains("    pub const CERTIFICATE_2: &[u8] = &[];"));
+    assert_eq!(std::fs::read(dir.join("ec_certificate_1.der")).unwrap(), keybox.ec_leaf().unwrap());
+    let private_key = keybox.ec_key().unwrap().private_key_bytes().unwrap();
+    assert_eq!(std::fs::read(dir.join("ec_private_key.der")).unwrap(), private_key);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,26 @@
+#![cfg(feature = "json")]
+
+use keybox_parser::json::{from_json, to_json};
+use keybox_parser::{parse_keybox_reader, KeyboxSignature, PrivateKeyBytes};
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+
+#[test]
+fn round_trips_through_json() {
+    let mut keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    keybox.signature = Some(KeyboxSignature {
+        algorithm: "sha256".to_string(),
+        value: vec![0xde, 0xad, 0xbe, 0xef],
+    });
+    keybox.format_version = Some("1.0".to_string());
+    let mut backup = PrivateKeyBytes::default();
+    backup.extend_from_slice(&[0x30, 0x03, 0x02, 0x01, 0x01]);
+    keybox.keys[0].extra_private_keys.push(backup);
+    keybox.keys[0].attributes.insert("purpose".to_string(), "attestation".to_string());
+    let decoded = from_json(&to_json(&keybox)).unwrap();
+
+    assert_eq!(decoded.device_id, keybox.device_id);
+    assert_eq!(decoded.keys, keybox.keys);
+    assert_eq!(decoded.signature, keybox.signature);
+    assert_eq!(decoded.format_version, keybox.format_version);
+}
@@ -0,0 +1,32 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use keybox_parser::{der_to_base64, der_to_pem, pem_to_der, pem_to_der_all, KeyboxError};
+
+const EC_CERTIFICATE: &[u8] = include_bytes!("fixtures/ec_certificate.der");
+
+#[test]
+fn round_trips_der_through_pem() {
+    let pem = der_to_pem(EC_CERTIFICATE, "CERTIFICATE");
+
+    assert!(pem.starts_with("-----BEGIN CERTIFICATE-----\n"));
+    assert!(pem.ends_with("\n-----END CERTIFICATE-----\n"));
+    assert!(pem.lines().all(|line| line.len() <= 64));
+    assert_eq!(pem_to_der(&pem).unwrap(), EC_CERTIFICATE);
+}
+
+#[test]
+fn encodes_with_the_given_engine() {
+    assert_eq!(der_to_base64(&[0xfb, 0xff], &URL_SAFE_NO_PAD), "-_8");
+    assert_eq!(pem_to_der("+/8").unwrap(), [0xfb, 0xff]);
+}
+
+#[test]
+fn splits_blocks_like_keybox_bodies() {
+    let pem = der_to_pem(EC_CERTIFICATE, "CERTIFICATE");
+    let chain = format!("subject=CN = leaf\n{}issuer=CN = root\n  {}", pem, pem.replace('\n', "\r\n  "));
+    assert_eq!(pem_to_der_all(&chain).unwrap(), [EC_CERTIFICATE, EC_CERTIFICATE]);
+    assert_eq!(pem_to_der(&chain).unwrap(), EC_CERTIFICATE);
+
+    assert!(pem_to_der_all(" \n").unwrap().is_empty());
+    let empty = "-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n";
+    assert!(matches!(pem_to_der(empty), Err(KeyboxError::MalformedKeybox(_))));
+}
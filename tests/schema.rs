@@ -0,0 +1,75 @@
+#![cfg(feature = "std")]
+
+use keybox_parser::schema::validate_structure_reader;
+use keybox_parser::{parse_keybox_reader, KeyboxError};
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+
+fn deviation(xml: &str) -> String {
+    match validate_structure_reader(xml.as_bytes()) {
+        Err(KeyboxError::MalformedKeybox(message)) => message,
+        other => panic!("expected a malformed keybox error, got {:?}", other),
+    }
+}
+
+#[test]
+fn accepts_expected_structure() {
+    validate_structure_reader(FIXTURE.as_bytes()).unwrap();
+    validate_structure_reader(include_str!("fixtures/keybox_algorithm_element.xml").as_bytes()).unwrap();
+    validate_structure_reader(include_str!("fixtures/keybox_reordered.xml").as_bytes()).unwrap();
+}
+
+// Strict builds validate the structure before parsing, so it must allow what the
+// parser reads
+#[test]
+fn accepts_repeated_private_keys() {
+    let end = FIXTURE.find("</PrivateKey>").unwrap() + "</PrivateKey>\n".len();
+    let backup = "<PrivateKey format=\"pem\">\nMAMCAQE=\n</PrivateKey>\n";
+    let xml = format!("{}{}{}", &FIXTURE[..end], backup, &FIXTURE[end..]);
+
+    validate_structure_reader(xml.as_bytes()).unwrap();
+    let keybox = parse_keybox_reader(xml.as_bytes()).unwrap().remove(0);
+    assert_eq!(keybox.keys[0].extra_private_keys.len(), 1);
+
+    let xml = xml.replacen("</PrivateKey>", "</PrivateKey>\n<Algorithm>ecdsa</Algorithm>\n<Algorithm/>", 1);
+    assert_eq!(deviation(&xml), "unexpected element at /AndroidAttestation/Keybox[1]/Key[1]/Algorithm");
+}
+
+#[test]
+fn reports_unexpected_nesting_with_its_path() {
+    let xml = FIXTURE.replacen("<Key algorithm=\"rsa\">", "<Key algorithm=\"rsa\">\n<Extra/>", 1);
+    assert_eq!(deviation(&xml), "unexpected element at /AndroidAttestation/Keybox[1]/Key[2]/Extra");
+
+    // A certificate outside its chain
+    let xml = FIXTURE.replacen("<CertificateChain>", "<Certificate/>\n<CertificateChain>", 1);
+    assert_eq!(deviation(&xml), "unexpected element at /AndroidAttestation/Keybox[1]/Key[1]/Certificate[1]");
+
+    let xml = FIXTURE.replacen("</CertificateChain>", "</CertificateChain>\n<CertificateChain/>", 1);
+    assert_eq!(deviation(&xml), "unexpected element at /AndroidAttestation/Keybox[1]/Key[1]/CertificateChain");
+
+    let xml = FIXTURE.replacen("<AndroidAttestation>", "<Keyboxes>", 1);
+    assert_eq!(deviation(&xml.replacen("</AndroidAttestation>", "</Keyboxes>", 1)), "unexpected element at /Keyboxes");
+}
+
+#[test]
+fn checks_only_the_keybox_subtree_of_a_larger_document() {
+    let embedded = include_str!("fixtures/keybox_embedded.xml");
+    validate_structure_reader(embedded.as_bytes()).unwrap();
+
+    let xml = embedded.replacen("<Keybox DeviceID=\"test-device\">", "<Keybox DeviceID=\"test-device\">\n<Extra/>", 1);
+    let expected = "unexpected element at /ProvisioningProfile/Attestation/AndroidAttestation/Keybox[1]/Extra";
+    assert_eq!(deviation(&xml), expected);
+
+    // A bare keybox is checked from its root
+    let start = FIXTURE.find("<Keybox ").unwrap();
+    let end = FIXTURE.find("</Keybox>").unwrap() + "</Keybox>".len();
+    validate_structure_reader(&FIXTURE.as_bytes()[start..end]).unwrap();
+    let xml = FIXTURE[start..end].replacen("</Key>", "</Key>\n<Extra/>", 1);
+    assert_eq!(deviation(&xml), "unexpected element at /Keybox[1]/Extra");
+}
+
+#[test]
+fn reports_stray_text() {
+    let xml = FIXTURE.replacen("</Key>", "stray\n</Key>", 1);
+    assert_eq!(deviation(&xml), "unexpected text in /AndroidAttestation/Keybox[1]/Key[1]");
+}
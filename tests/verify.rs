@@ -0,0 +1,81 @@
+#![cfg(feature = "verify")]
+
+use keybox_parser::certificate::{ec_public_key, verify_against_roots};
+use keybox_parser::{der_to_pem, parse_keybox_reader, Keybox, KeyboxBuilder, KeyboxError};
+
+const FIXTURE: &str = include_str!("fixtures/keybox.xml");
+const EC_CERTIFICATE: &[u8] = include_bytes!("fixtures/ec_certificate.der");
+// A chain generated for these tests, leaf first, issued by TEST_ROOT through an
+// intermediate, all valid until 2126
+const TEST_LEAF: &[u8] = include_bytes!("fixtures/test_leaf.der");
+const TEST_INTERMEDIATE: &[u8] = include_bytes!("fixtures/test_intermediate.der");
+const TEST_ROOT: &[u8] = include_bytes!("fixtures/test_root.der");
+// TEST_LEAF's key and subject, issued by the intermediate for 2000 only
+const TEST_EXPIRED_LEAF: &[u8] = include_bytes!("fixtures/test_expired_leaf.der");
+
+fn test_chain(certificates: &[&[u8]]) -> Keybox {
+    certificates.iter().fold(KeyboxBuilder::new(), |builder, cert| builder.add_ec_certificate(*cert)).build()
+}
+
+fn failed_link(keybox: &Keybox, roots: &[&[u8]]) -> (String, String) {
+    match verify_against_roots(keybox, roots) {
+        Err(KeyboxError::InvalidCertificate { context, reason }) => (context, reason),
+        other => panic!("expected an invalid certificate error, got {:?}", other),
+    }
+}
+
+#[test]
+fn accepts_chains_issued_by_a_root() {
+    verify_against_roots(&test_chain(&[TEST_LEAF, TEST_INTERMEDIATE]), &[EC_CERTIFICATE, TEST_ROOT]).unwrap();
+    // A chain may also end with the root itself
+    verify_against_roots(&test_chain(&[TEST_LEAF, TEST_INTERMEDIATE, TEST_ROOT]), &[TEST_ROOT]).unwrap();
+}
+
+#[test]
+fn names_the_link_that_fails() {
+    let (context, reason) = failed_link(&test_chain(&[TEST_LEAF, TEST_INTERMEDIATE]), &[EC_CERTIFICATE]);
+    assert_eq!(context, "key 1 (EC) certificate 2");
+    assert!(reason.starts_with("is not issued by any trusted root"), "{}", reason);
+
+    // The leaf was not signed by the next certificate
+    let (context, reason) = failed_link(&test_chain(&[TEST_LEAF, TEST_ROOT]), &[TEST_ROOT]);
+    assert_eq!(context, "key 1 (EC) certificate 1");
+    assert!(reason.starts_with("signature does not verify against certificate 2"), "{}", reason);
+
+    let (context, reason) = failed_link(&test_chain(&[TEST_EXPIRED_LEAF, TEST_INTERMEDIATE]), &[TEST_ROOT]);
+    assert_eq!(context, "key 1 (EC) certificate 1");
+    assert!(reason.starts_with("not valid now"), "{}", reason);
+
+    assert!(matches!(verify_against_roots(&test_chain(&[TEST_LEAF]), &[]), Err(KeyboxError::Config(_))));
+}
+
+#[test]
+fn derives_the_ec_public_key_from_leaf_or_private_key() {
+    let expected = "047fc43f0722804b7353785a4e4f3ff456951404eae9e99688cd65875c85874c0e\
+                    8511320710012c699b005fe169b9395083d926978a6cc945757f73dfba30d52c";
+    let keybox = parse_keybox_reader(FIXTURE.as_bytes()).unwrap().remove(0);
+    let mut key = keybox.ec_key().unwrap().clone();
+    let hex = |point: Vec<u8>| point.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+
+    assert_eq!(ec_public_key(&key).unwrap().map(hex).as_deref(), Some(expected));
+    key.certificates.clear();
+    assert_eq!(ec_public_key(&key).unwrap().map(hex).as_deref(), Some(expected));
+    key.private_key = None;
+    assert_eq!(ec_public_key(&key).unwrap(), None);
+}
+
+#[test]
+fn rejects_bytes_after_the_certificate() {
+    let pem = der_to_pem(EC_CERTIFICATE, "CERTIFICATE");
+    let concatenated = der_to_pem(&[EC_CERTIFICATE, EC_CERTIFICATE].concat(), "CERTIFICATE");
+    assert!(FIXTURE.contains(&pem));
+    match parse_keybox_reader(FIXTURE.replacen(&pem, &concatenated, 1).as_bytes()) {
+        Err(KeyboxError::InvalidCertificate { context, reason }) => {
+            assert_eq!(context, "keybox 1 EC certificate 1");
+            let len = EC_CERTIFICATE.len();
+            let expected = format!("DER declares {} bytes but {} were decoded", len, 2 * len);
+            assert_eq!(reason, format!("{}, leaving {} trailing bytes", expected, len));
+        }
+        other => panic!("expected an invalid certificate error, got {:?}", other),
+    }
+}